@@ -0,0 +1,750 @@
+//! Assembler for the syntax produced by `Instruction`'s `Display` implementation
+//!
+//! ```text
+//! ; comment
+//! SPEED = 2                   ; constant
+//! loop:                       ; label
+//!     LD   V1, SPEED + 1
+//!     DRW  V0, V1, 5
+//!     JP   loop
+//!     db   0x3C, 0b01000010   ; bytes
+//!     dw   0x1234             ; big-endian words
+//!     include "sprites.src"
+//! ```
+//!
+//! Nibble operands (`DRW`, `SCD`, `PLANE`, `LD [I], n`) are read as hex if they are a single digit.
+
+use crate::instruction::Instruction;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const START: usize = 0x200;
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Punct(char),
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    text: String,
+    column: usize,
+}
+
+/// Where a line comes from, for error reporting
+#[derive(Clone, Debug)]
+struct Location {
+    file: usize,
+    line: usize,
+}
+
+enum Item {
+    Instruction {
+        mnemonic: Token,
+        operands: Vec<Vec<Token>>,
+    },
+    Bytes(Vec<Vec<Token>>),
+    Words(Vec<Vec<Token>>),
+}
+
+struct Line {
+    location: Location,
+    addr: usize,
+    item: Item,
+}
+
+enum Symbol {
+    Label(usize),
+    Constant(Location, Vec<Token>),
+}
+
+struct Assembler {
+    files: Vec<String>,
+    lines: Vec<Line>,
+    symbols: HashMap<String, Symbol>,
+    addr: usize,
+}
+
+/// Assembles `source` into a ROM loaded at 0x200, includes are resolved relative to `path`
+pub fn assemble(source: &str, path: &Path) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler {
+        files: Vec::new(),
+        lines: Vec::new(),
+        symbols: HashMap::new(),
+        addr: START,
+    };
+
+    asm.parse_file(source, path, 0)?;
+    asm.emit()
+}
+
+pub fn assemble_file(path: impl AsRef<Path>) -> Result<Vec<u8>, AsmError> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: e.to_string(),
+    })?;
+
+    assemble(&source, path)
+}
+
+impl Assembler {
+    fn error(&self, location: &Location, column: usize, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.files[location.file].clone(),
+            line: location.line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// First pass: tokenizes lines, assigns addresses and collects symbols
+    fn parse_file(&mut self, source: &str, path: &Path, depth: usize) -> Result<(), AsmError> {
+        let file = self.files.len();
+        self.files.push(path.display().to_string());
+
+        for (line, text) in source.lines().enumerate() {
+            let location = Location {
+                file,
+                line: line + 1,
+            };
+            let tokens = tokenize(text)
+                .map_err(|column| self.error(&location, column, "unexpected character"))?;
+            self.parse_line(location, tokens, path, depth)?;
+        }
+
+        Ok(())
+    }
+
+    fn parse_line(
+        &mut self,
+        location: Location,
+        mut tokens: Vec<Token>,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        // Labels
+        while tokens.len() >= 2 && tokens[1].kind == TokenKind::Punct(':') {
+            let name = match &tokens[0].kind {
+                TokenKind::Ident(name) => name.clone(),
+                _ => return Err(self.error(&location, tokens[0].column, "invalid label")),
+            };
+            self.define(&location, &tokens[0], name, Symbol::Label(self.addr))?;
+            tokens.drain(0..2);
+        }
+
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        // Constants
+        if tokens.len() >= 2 && tokens[1].kind == TokenKind::Punct('=') {
+            let name = match &tokens[0].kind {
+                TokenKind::Ident(name) => name.clone(),
+                _ => return Err(self.error(&location, tokens[0].column, "invalid constant")),
+            };
+            if tokens.len() == 2 {
+                return Err(self.error(&location, tokens[1].column, "missing value"));
+            }
+            let value = tokens.split_off(2);
+            let symbol = Symbol::Constant(location.clone(), value);
+            return self.define(&location, &tokens[0], name, symbol);
+        }
+
+        let mnemonic = tokens.remove(0);
+        let name = match &mnemonic.kind {
+            TokenKind::Ident(name) => name.to_uppercase(),
+            _ => return Err(self.error(&location, mnemonic.column, "expected an instruction")),
+        };
+        let operands = split_operands(tokens)
+            .map_err(|column| self.error(&location, column, "missing operand"))?;
+
+        let (item, size) = match name.as_str() {
+            "DB" => {
+                let size = operands.len();
+                (Item::Bytes(operands), size)
+            },
+            "DW" => {
+                let size = operands.len() * 2;
+                (Item::Words(operands), size)
+            },
+            "INCLUDE" => return self.include(&location, &mnemonic, &operands, path, depth),
+            _ => {
+                // `LD I, LONG addr` is followed by the address
+                let long = name == "LD"
+                    && operands.len() == 2
+                    && operands[1].len() > 1
+                    && is_keyword(&operands[1][0], "LONG");
+                let size = if long { 4 } else { 2 };
+                (Item::Instruction { mnemonic, operands }, size)
+            },
+        };
+
+        self.lines.push(Line {
+            location,
+            addr: self.addr,
+            item,
+        });
+        self.addr += size;
+
+        Ok(())
+    }
+
+    fn define(
+        &mut self,
+        location: &Location,
+        token: &Token,
+        name: String,
+        symbol: Symbol,
+    ) -> Result<(), AsmError> {
+        if operand_keyword(&name).is_some() {
+            return Err(self.error(location, token.column, format!("'{}' is reserved", name)));
+        }
+        if self.symbols.contains_key(&name) {
+            return Err(self.error(
+                location,
+                token.column,
+                format!("'{}' is already defined", name),
+            ));
+        }
+        self.symbols.insert(name, symbol);
+
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        location: &Location,
+        mnemonic: &Token,
+        operands: &[Vec<Token>],
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let file = match operands {
+            [op] if op.len() == 1 => match &op[0].kind {
+                TokenKind::Str(file) => file,
+                _ => return Err(self.error(location, op[0].column, "expected a file name")),
+            },
+            _ => return Err(self.error(location, mnemonic.column, "expected a file name")),
+        };
+
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(self.error(location, mnemonic.column, "includes are nested too deep"));
+        }
+
+        let include_path: PathBuf = path.parent().unwrap_or_else(|| Path::new("")).join(file);
+        let source = std::fs::read_to_string(&include_path).map_err(|e| {
+            self.error(
+                location,
+                operands[0][0].column,
+                format!("could not read {}: {}", include_path.display(), e),
+            )
+        })?;
+
+        self.parse_file(&source, &include_path, depth + 1)
+    }
+
+    /// Second pass: evaluates operands and produces the ROM
+    fn emit(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = vec![0; self.addr - START];
+
+        for line in self.lines.iter() {
+            let location = &line.location;
+            let pos = line.addr - START;
+
+            match &line.item {
+                Item::Bytes(values) => {
+                    for (i, value) in values.iter().enumerate() {
+                        rom[pos + i] = self.eval_range(location, value, -0x80, 0xFF)? as u8;
+                    }
+                },
+                Item::Words(values) => {
+                    for (i, value) in values.iter().enumerate() {
+                        let word = self.eval_range(location, value, -0x8000, 0xFFFF)? as u16;
+                        rom[pos + i * 2..pos + i * 2 + 2].copy_from_slice(&word.to_be_bytes());
+                    }
+                },
+                Item::Instruction { mnemonic, operands } => {
+                    let (instr, long) = self.instruction(location, mnemonic, operands)?;
+                    rom[pos..pos + 2].copy_from_slice(&instr.encode());
+                    if let Some(long) = long {
+                        rom[pos + 2..pos + 4].copy_from_slice(&(long as u16).to_be_bytes());
+                    }
+                },
+            }
+        }
+
+        Ok(rom)
+    }
+
+    fn eval(&self, location: &Location, tokens: &[Token], depth: usize) -> Result<i64, AsmError> {
+        let mut value = 0;
+        let mut sign = 1;
+        let mut expect_term = true;
+
+        for token in tokens {
+            match (&token.kind, expect_term) {
+                (TokenKind::Punct('-'), true) => sign = -sign,
+                (TokenKind::Punct('+'), true) => {},
+                (TokenKind::Punct('+'), false) => {
+                    sign = 1;
+                    expect_term = true;
+                },
+                (TokenKind::Punct('-'), false) => {
+                    sign = -1;
+                    expect_term = true;
+                },
+                (TokenKind::Number(n), true) => {
+                    value += sign * n;
+                    expect_term = false;
+                },
+                (TokenKind::Ident(name), true) => {
+                    value += sign * self.symbol(location, token, name, depth)?;
+                    expect_term = false;
+                },
+                _ => return Err(self.error(location, token.column, "invalid expression")),
+            }
+        }
+
+        if expect_term {
+            let column = tokens.last().map(|t| t.column).unwrap_or(0);
+            return Err(self.error(location, column, "incomplete expression"));
+        }
+
+        Ok(value)
+    }
+
+    fn symbol(
+        &self,
+        location: &Location,
+        token: &Token,
+        name: &str,
+        depth: usize,
+    ) -> Result<i64, AsmError> {
+        match self.symbols.get(name) {
+            Some(Symbol::Label(addr)) => Ok(*addr as _),
+            Some(Symbol::Constant(constant_location, value)) => {
+                if depth > self.symbols.len() {
+                    return Err(self.error(location, token.column, "constant refers to itself"));
+                }
+                self.eval(constant_location, value, depth + 1)
+            },
+            None => Err(self.error(
+                location,
+                token.column,
+                format!("undefined symbol '{}'", name),
+            )),
+        }
+    }
+
+    fn eval_range(
+        &self,
+        location: &Location,
+        tokens: &[Token],
+        min: i64,
+        max: i64,
+    ) -> Result<i64, AsmError> {
+        let value = self.eval(location, tokens, 0)?;
+        if value < min || value > max {
+            return Err(self.error(
+                location,
+                tokens[0].column,
+                format!("value {} out of range {}..={}", value, min, max),
+            ));
+        }
+        Ok(value & max)
+    }
+
+    fn instruction(
+        &self,
+        location: &Location,
+        mnemonic: &Token,
+        operands: &[Vec<Token>],
+    ) -> Result<(Instruction, Option<i64>), AsmError> {
+        use Instruction::*;
+        use Operand::*;
+
+        let ops: Vec<_> = operands.iter().map(|op| operand(op)).collect();
+        let name = mnemonic.text.to_uppercase();
+
+        let addr = |i: usize| {
+            self.eval_range(location, &operands[i], 0, 0xFFF)
+                .map(|v| v as usize)
+        };
+        let byte = |i: usize| {
+            self.eval_range(location, &operands[i], -0x80, 0xFF)
+                .map(|v| v as u8)
+        };
+        let nibble = |i: usize| match hex_digit(&operands[i]) {
+            Some(n) => Ok(n),
+            None => self
+                .eval_range(location, &operands[i], 0, 0xF)
+                .map(|v| v as u8),
+        };
+        let register = |i: usize| match ops[i] {
+            Reg(x) => Ok(x),
+            _ => match hex_digit(&operands[i]) {
+                Some(x) => Ok(x as usize),
+                None => Err(self.error(location, operands[i][0].column, "expected a register")),
+            },
+        };
+
+        let instr = match (name.as_str(), &ops[..]) {
+            ("CLS", []) => Clear,
+            ("RET", []) => Return,
+            ("SCR", []) => ScrollRight,
+            ("SCL", []) => ScrollLeft,
+            ("LOW", []) => LowRes,
+            ("HIGH", []) => HighRes,
+            ("EXIT", []) => Exit,
+            ("AUDIO", []) => LoadAudio,
+
+            ("JP", [Value]) => Jump(addr(0)?),
+            ("JP", [Reg(x), Value]) => {
+                let a = addr(1)?;
+                if *x != 0 && *x != a >> 8 {
+                    return Err(self.error(location, operands[0][0].column, "expected V0"));
+                }
+                JumpPlus(a)
+            },
+            ("CALL", [Value]) => Call(addr(0)?),
+
+            ("SE", [Reg(x), Reg(y)]) => SkipEq(*x, *y),
+            ("SE", [Reg(x), Value]) => SkipEqByte(*x, byte(1)?),
+            ("SNE", [Reg(x), Reg(y)]) => SkipNe(*x, *y),
+            ("SNE", [Reg(x), Value]) => SkipNeByte(*x, byte(1)?),
+            ("SKP", [Reg(x)]) => SkipPressed(*x),
+            ("SKNP", [Reg(x)]) => SkipNPressed(*x),
+
+            ("LD", [Reg(x), Reg(y)]) => Copy(*x, *y),
+            ("LD", [Reg(x), Value]) => LoadByte(*x, byte(1)?),
+            ("LD", [Reg(x), Keyword("DT")]) => LoadDelay(*x),
+            ("LD", [Reg(x), Keyword("K")]) => LoadPressed(*x),
+            ("LD", [Reg(x), Keyword("R")]) => RestoreFlags(*x),
+            ("LD", [Keyword("DT"), Reg(x)]) => SetDelay(*x),
+            ("LD", [Keyword("ST"), Reg(x)]) => SetSound(*x),
+            ("LD", [Keyword("I"), Value]) => LoadI(addr(1)?),
+            ("LD", [Keyword("I"), Keyword("LONG")]) => LongLoadI,
+            ("LD", [Keyword("I"), Long]) => {
+                let a = self.eval_range(location, &operands[1][1..], 0, 0xFFFF)?;
+                return Ok((LongLoadI, Some(a)));
+            },
+            ("LD", [Keyword("F"), Reg(x)]) => SetSprite(*x),
+            ("LD", [Keyword("HF"), Reg(x)]) => SetBigSprite(*x),
+            ("LD", [Keyword("B"), Reg(x)]) => StoreBCD(*x),
+            ("LD", [Keyword("R"), Reg(x)]) => StoreFlags(*x),
+            ("LD", [IndirectI, _]) => StoreRegisters(register(1)?),
+            ("LD", [_, IndirectI]) => RestoreRegisters(register(0)?),
+
+            ("RND", [Reg(x), Value]) => LoadRandom(*x, byte(1)?),
+            ("ADD", [Reg(x), Value]) => AddByte(*x, byte(1)?),
+            ("ADD", [Reg(x), Reg(y)]) => Add(*x, *y),
+            ("ADD", [Keyword("I"), Reg(x)]) => AddToI(*x),
+            ("OR", [Reg(x), Reg(y)]) => Or(*x, *y),
+            ("AND", [Reg(x), Reg(y)]) => And(*x, *y),
+            ("XOR", [Reg(x), Reg(y)]) => Xor(*x, *y),
+            ("SUB", [Reg(x), Reg(y)]) => Sub(*x, *y),
+            ("SUBN", [Reg(x), Reg(y)]) => Subn(*x, *y),
+            ("SHR", [Reg(x)]) => Shr(*x, *x),
+            ("SHR", [Reg(x), Reg(y)]) => Shr(*x, *y),
+            ("SHL", [Reg(x)]) => Shl(*x, *x),
+            ("SHL", [Reg(x), Reg(y)]) => Shl(*x, *y),
+
+            ("DRW", [Reg(x), Reg(y), _]) => Draw(*x, *y, nibble(2)?),
+            ("SCD", [_]) => ScrollDown(nibble(0)?),
+
+            ("SAVE", [Reg(x), Reg(y)]) => SaveRange(*x, *y),
+            ("LOAD", [Reg(x), Reg(y)]) => LoadRange(*x, *y),
+            ("PLANE", [_]) => Plane(nibble(0)?),
+            ("PITCH", [Reg(x)]) => SetPitch(*x),
+
+            ("JP", _)
+            | ("CALL", _)
+            | ("SE", _)
+            | ("SNE", _)
+            | ("SKP", _)
+            | ("SKNP", _)
+            | ("LD", _)
+            | ("RND", _)
+            | ("ADD", _)
+            | ("OR", _)
+            | ("AND", _)
+            | ("XOR", _)
+            | ("SUB", _)
+            | ("SUBN", _)
+            | ("SHR", _)
+            | ("SHL", _)
+            | ("DRW", _)
+            | ("SCD", _)
+            | ("SAVE", _)
+            | ("LOAD", _)
+            | ("PLANE", _)
+            | ("PITCH", _)
+            | ("CLS", _)
+            | ("RET", _)
+            | ("SCR", _)
+            | ("SCL", _)
+            | ("LOW", _)
+            | ("HIGH", _)
+            | ("EXIT", _)
+            | ("AUDIO", _) => {
+                return Err(self.error(
+                    location,
+                    mnemonic.column,
+                    format!("invalid operands for {}", name),
+                ))
+            },
+            _ => {
+                return Err(self.error(
+                    location,
+                    mnemonic.column,
+                    format!("unknown instruction '{}'", mnemonic.text),
+                ))
+            },
+        };
+
+        Ok((instr, None))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operand {
+    Reg(usize),
+    Keyword(&'static str),
+    /// `[I]`
+    IndirectI,
+    /// `LONG addr`
+    Long,
+    Value,
+}
+
+fn operand(tokens: &[Token]) -> Operand {
+    match tokens {
+        [t] => {
+            if let TokenKind::Ident(name) = &t.kind {
+                if let Some(x) = register_index(name) {
+                    return Operand::Reg(x);
+                }
+                if let Some(keyword) = operand_keyword(name) {
+                    return Operand::Keyword(keyword);
+                }
+            }
+            Operand::Value
+        },
+        [open, i, close]
+            if open.kind == TokenKind::Punct('[')
+                && is_keyword(i, "I")
+                && close.kind == TokenKind::Punct(']') =>
+        {
+            Operand::IndirectI
+        },
+        [long, ..] if is_keyword(long, "LONG") => Operand::Long,
+        _ => Operand::Value,
+    }
+}
+
+fn operand_keyword(name: &str) -> Option<&'static str> {
+    const KEYWORDS: [&str; 9] = ["I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"];
+    let upper = name.to_uppercase();
+    KEYWORDS.iter().find(|k| **k == upper).copied()
+}
+
+fn is_keyword(token: &Token, keyword: &str) -> bool {
+    match &token.kind {
+        TokenKind::Ident(name) => name.eq_ignore_ascii_case(keyword),
+        _ => false,
+    }
+}
+
+/// `V0`-`VF`
+fn register_index(name: &str) -> Option<usize> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(x), None) | (Some('v'), Some(x), None) => {
+            x.to_digit(16).map(|x| x as usize)
+        },
+        _ => None,
+    }
+}
+
+/// A single hex digit, as `Display` prints nibble operands
+fn hex_digit(tokens: &[Token]) -> Option<u8> {
+    match tokens {
+        [t] if t.text.len() == 1 => t.text.chars().next()?.to_digit(16).map(|d| d as u8),
+        _ => None,
+    }
+}
+
+/// Splits tokens on commas, returns the column of an empty operand on error
+fn split_operands(tokens: Vec<Token>) -> Result<Vec<Vec<Token>>, usize> {
+    let mut operands = Vec::new();
+    if tokens.is_empty() {
+        return Ok(operands);
+    }
+
+    let mut current = Vec::new();
+    for token in tokens {
+        if token.kind == TokenKind::Punct(',') {
+            if current.is_empty() {
+                return Err(token.column);
+            }
+            operands.push(std::mem::take(&mut current));
+        } else {
+            current.push(token);
+        }
+    }
+    match current.is_empty() {
+        true => Err(operands.last().map(|o| o[0].column).unwrap_or(0)),
+        false => {
+            operands.push(current);
+            Ok(operands)
+        },
+    }
+}
+
+/// Returns the column of the offending character on error
+fn tokenize(line: &str) -> Result<Vec<Token>, usize> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let start = pos;
+
+        let kind = if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            pos += 1;
+            continue;
+        } else if c == '"' {
+            pos += 1;
+            while pos < chars.len() && chars[pos] != '"' {
+                pos += 1;
+            }
+            if pos == chars.len() {
+                return Err(start + 1);
+            }
+            pos += 1;
+            TokenKind::Str(chars[start + 1..pos - 1].iter().collect())
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$' {
+            while pos < chars.len()
+                && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_' || chars[pos] == '.')
+                || pos == start
+            {
+                pos += 1;
+            }
+            let text: String = chars[start..pos].iter().collect();
+            match parse_number(&text) {
+                Some(n) => TokenKind::Number(n),
+                None if c.is_ascii_digit() || c == '$' => return Err(start + 1),
+                None => TokenKind::Ident(text),
+            }
+        } else if ",:[]+-=".contains(c) {
+            pos += 1;
+            TokenKind::Punct(c)
+        } else {
+            return Err(start + 1);
+        };
+
+        tokens.push(Token {
+            kind,
+            text: chars[start..pos].iter().collect(),
+            column: start + 1,
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// Parses `0x1F`, `$1F`, `0b11111` or `31`
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asm(source: &str) -> Result<Vec<u8>, AsmError> {
+        assemble(source, Path::new("test.src"))
+    }
+
+    #[test]
+    fn labels_and_constants() {
+        let rom = asm("SPEED = 2\nloop:\n    LD   V1, SPEED + 1\n    JP   loop\n    CALL end\nend:\n    RET\n")
+            .unwrap();
+        assert_eq!(rom, [0x61, 0x03, 0x12, 0x00, 0x22, 0x06, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn bytes_and_words() {
+        let rom = asm("db 0x3C, 0b01000010, 7\ndw 0x1234\n").unwrap();
+        assert_eq!(rom, [0x3C, 0x42, 0x07, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn include() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("sprites.src"), "sprite:\n    db 0xFF\n").unwrap();
+
+        let source = "    LD   I, sprite\n    include \"sprites.src\"\n";
+        let rom = assemble(source, &dir.join("main.src"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rom.unwrap(), [0xA2, 0x02, 0xFF]);
+    }
+
+    #[test]
+    fn error_location() {
+        let e = asm("    CLS\n    LD   V1, nothing\n").unwrap_err();
+        assert_eq!((e.file.as_str(), e.line, e.column), ("test.src", 2, 14));
+        assert!(e.message.contains("nothing"), "{}", e.message);
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        let rom = [
+            0x00, 0xE0, 0xA2, 0x12, 0x60, 0x05, 0x61, 0x0A, 0xD0, 0x15, 0x70, 0x01, 0x30, 0x20,
+            0x12, 0x06, 0x12, 0x0E, 0x3C, 0x42, 0x81, 0x81, 0x81, 0x42, 0x3C,
+        ];
+        let source = crate::disasm::disassemble(&rom);
+        assert_eq!(asm(&source).unwrap(), rom, "{}", source);
+    }
+}
@@ -0,0 +1,277 @@
+//! PNG screenshots and GIF recordings of the screen
+//!
+//! Screenshots are the 64x32 or 128x64 screen times the scale. GIFs are the whole 128x64 buffer,
+//! low-res pixels being 2x2, times the scale, to keep their size when the resolution changes.
+
+use crate::screen::{HEIGHT, WIDTH};
+use std::fs::File;
+use std::io::BufWriter;
+
+/// Scale and colors of the images
+#[derive(Clone, Copy, Debug)]
+pub struct Style {
+    pub scale: usize,
+    /// RGB colors for every combination of the two bitplanes
+    pub palette: [[u8; 3]; 4],
+}
+
+impl Default for Style {
+    /// Same colors as the window
+    fn default() -> Self {
+        Self {
+            scale: 4,
+            palette: [[0, 0, 0], [255, 255, 255], [85, 85, 85], [170, 170, 170]],
+        }
+    }
+}
+
+impl Style {
+    /// Parses four comma separated hex colors, like `000000,ffffff,555555,aaaaaa`
+    pub fn parse_palette(s: &str) -> Option<[[u8; 3]; 4]> {
+        let mut palette = [[0; 3]; 4];
+        let mut colors = s.split(',');
+        for color in palette.iter_mut() {
+            let hex = colors.next()?.trim();
+            let hex = hex.strip_prefix('#').unwrap_or(hex);
+            let rgb = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)?;
+            color.copy_from_slice(&rgb.to_be_bytes()[1..]);
+        }
+        colors.next().is_none().then_some(palette)
+    }
+
+    /// Of the 64x32 screen unless `hires`
+    fn size(&self, hires: bool) -> (usize, usize) {
+        let (width, height) = if hires {
+            (WIDTH, HEIGHT)
+        } else {
+            (WIDTH / 2, HEIGHT / 2)
+        };
+        (width * self.scale, height * self.scale)
+    }
+
+    /// Palette indices of the scaled image, of the 64x32 screen unless `hires`
+    fn pixels(&self, buffer: &[[u8; WIDTH]; HEIGHT], hires: bool) -> Vec<u8> {
+        let (width, height) = self.size(hires);
+        // Low-res pixels are 2x2 in the buffer
+        let step = if hires { 1 } else { 2 };
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in buffer.iter().step_by(step) {
+            let line = row
+                .iter()
+                .step_by(step)
+                .flat_map(|pix| std::iter::repeat_n(pix & 3, self.scale))
+                .collect::<Vec<_>>();
+            for _ in 0..self.scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+        pixels
+    }
+}
+
+/// Writes the screen to a PNG file, 64x32 pixels times the scale unless `hires`
+pub fn screenshot(
+    path: &str,
+    buffer: &[[u8; WIDTH]; HEIGHT],
+    hires: bool,
+    style: Style,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("could not create {}: {}", path, e))?;
+    let (width, height) = style.size(hires);
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as _, height as _);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(style.palette.concat());
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&style.pixels(buffer, hires)))
+        .map_err(|e| format!("could not write {}: {}", path, e))
+}
+
+/// Animated GIF of the screen changes, but the ones replaced too fast to be shown
+pub struct Gif {
+    path: String,
+    encoder: gif::Encoder<BufWriter<File>>,
+    style: Style,
+    /// The last screen and when it was shown in hundredths of a second, written once the next
+    /// one tells how long it lasted
+    pending: Option<(Vec<u8>, u64)>,
+}
+
+/// GIF delays are in hundredths of a second and viewers slow down shorter ones, screens lasting
+/// less are replaced by the next one
+const MIN_DELAY: u64 = 2;
+
+impl Gif {
+    pub fn create(path: &str, style: Style) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("could not create {}: {}", path, e))?;
+        let (width, height) = style.size(true);
+
+        let mut encoder = gif::Encoder::new(
+            BufWriter::new(file),
+            width as _,
+            height as _,
+            &style.palette.concat(),
+        )
+        .map_err(|e| format!("could not write {}: {}", path, e))?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(|e| format!("could not write {}: {}", path, e))?;
+
+        Ok(Self {
+            path: path.to_string(),
+            encoder,
+            style,
+            pending: None,
+        })
+    }
+
+    /// Adds the screen shown from the 60hz frame `number` on
+    pub fn frame(&mut self, number: u64, buffer: &[[u8; WIDTH]; HEIGHT]) -> Result<(), String> {
+        // Rounded from the start so the delays add up
+        let time = (number * 100 + 30) / 60;
+        let pixels = self.style.pixels(buffer, true);
+
+        match self.pending.take() {
+            Some((previous, start)) if previous == pixels => self.pending = Some((previous, start)),
+            Some((_, start)) if time.saturating_sub(start) < MIN_DELAY => {
+                self.pending = Some((pixels, start))
+            },
+            Some((previous, start)) => {
+                self.write(&previous, time.saturating_sub(start))?;
+                self.pending = Some((pixels, time));
+            },
+            None => self.pending = Some((pixels, time)),
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last screen, shown until the frame `number`
+    pub fn finish(mut self, number: u64) -> Result<(), String> {
+        if let Some((pixels, start)) = self.pending.take() {
+            let time = (number * 100 + 30) / 60;
+            self.write(&pixels, time.saturating_sub(start).max(MIN_DELAY))?;
+        }
+
+        let path = self.path;
+        self.encoder
+            .into_inner()
+            .map_err(|e| e.to_string())
+            .and_then(|mut file| std::io::Write::flush(&mut file).map_err(|e| e.to_string()))
+            .map_err(|e| format!("could not write {}: {}", path, e))
+    }
+
+    fn write(&mut self, pixels: &[u8], delay: u64) -> Result<(), String> {
+        let (width, height) = self.style.size(true);
+
+        // Split in frames of the longest delay
+        let mut left = delay;
+        while left > 0 {
+            let delay = left.min(u16::MAX as u64);
+            left -= delay;
+
+            let frame = gif::Frame {
+                width: width as _,
+                height: height as _,
+                delay: delay as _,
+                buffer: pixels.into(),
+                ..gif::Frame::default()
+            };
+            self.encoder
+                .write_frame(&frame)
+                .map_err(|e| format!("could not write {}: {}", self.path, e))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    /// Delays of the frames of a recording where the screen changes every `every` frames
+    fn delays(every: u64) -> Vec<u16> {
+        let path =
+            std::env::temp_dir().join(format!("chip8-gif-{}-{}.gif", std::process::id(), every));
+        let path = path.to_str().unwrap();
+        let style = Style {
+            scale: 1,
+            ..Style::default()
+        };
+
+        let mut gif = Gif::create(path, style).unwrap();
+        let mut buffer = [[0; WIDTH]; HEIGHT];
+        for number in 0..60 {
+            buffer[0][0] = (number / every % 2) as u8;
+            gif.frame(number, &buffer).unwrap();
+        }
+        gif.finish(60).unwrap();
+
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(File::open(path).unwrap())
+            .unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        std::fs::remove_file(path).unwrap();
+        delays
+    }
+
+    #[test]
+    fn delays_add_up_to_a_second() {
+        for every in [1, 2, 7, 60].iter() {
+            let delays = delays(*every);
+            assert_eq!(delays.iter().sum::<u16>(), 100, "every {} frames", every);
+            assert!(delays.iter().all(|d| *d as u64 >= MIN_DELAY));
+        }
+        assert_eq!(delays(60), [100]);
+    }
+
+    /// Size and palette indices of a screenshot at scale 2
+    fn screenshot_pixels(buffer: &[[u8; WIDTH]; HEIGHT], hires: bool) -> (u32, u32, Vec<u8>) {
+        let path =
+            std::env::temp_dir().join(format!("chip8-shot-{}-{}.png", std::process::id(), hires));
+        let path = path.to_str().unwrap();
+        let style = Style {
+            scale: 2,
+            ..Style::default()
+        };
+        screenshot(path, buffer, hires, style).unwrap();
+
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path).unwrap()));
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        std::fs::remove_file(path).unwrap();
+        (info.width, info.height, pixels)
+    }
+
+    #[test]
+    fn screenshot_scale() {
+        // A low-res pixel of plane 2 at (1, 0)
+        let mut buffer = [[0; WIDTH]; HEIGHT];
+        for row in &mut buffer[0..2] {
+            row[2..4].copy_from_slice(&[2, 2]);
+        }
+
+        let (width, height, pixels) = screenshot_pixels(&buffer, false);
+        assert_eq!((width, height), (128, 64));
+        for row in pixels.chunks(128).take(2) {
+            assert_eq!(row[..5], [0, 0, 2, 2, 0]);
+        }
+        assert_eq!(pixels[128 * 2..128 * 2 + 5], [0; 5]);
+
+        let (width, height, pixels) = screenshot_pixels(&buffer, true);
+        assert_eq!((width, height), (256, 128));
+        assert_eq!(pixels[..10], [0, 0, 0, 0, 2, 2, 2, 2, 0, 0]);
+        assert_eq!(pixels[256 * 4..256 * 4 + 10], [0; 10]);
+    }
+}
@@ -0,0 +1,320 @@
+use crate::instruction::Instruction;
+use crate::machine::{Access, Machine, Watchpoint};
+use std::collections::BTreeSet;
+use std::sync::mpsc;
+
+pub enum Command {
+    Pause,
+    Continue,
+    Step,
+    StepOver,
+    StepOut,
+    AddBreakpoint(usize),
+    RemoveBreakpoint(usize),
+    ListBreakpoints,
+    /// Run a closure on the machine thread, between two steps
+    Inspect(Box<dyn FnOnce(&mut Machine) + Send>),
+}
+
+/// Why the machine stopped running
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    /// By a command, a breakpoint, a watchpoint or a finished step
+    Paused,
+    /// By `00FD`, the machine won't run again
+    Halted,
+    /// By an error, the machine won't run again
+    Crashed,
+}
+
+enum State {
+    Running,
+    Paused,
+    Step,
+    /// Pause once the stack is at most `sp` deep
+    StepOver {
+        sp: usize,
+    },
+    /// Pause once the stack is less than `sp` deep
+    StepOut {
+        sp: usize,
+    },
+}
+
+pub struct Debugger {
+    commands: mpsc::Receiver<Command>,
+    state: State,
+    breakpoints: BTreeSet<usize>,
+    stop_listeners: Vec<mpsc::Sender<Stop>>,
+}
+
+impl Debugger {
+    /// Creates a paused debugger, controlled through the returned sender
+    pub fn new() -> (Self, mpsc::Sender<Command>) {
+        let (sender, commands) = mpsc::channel();
+
+        let debugger = Self {
+            commands,
+            state: State::Paused,
+            breakpoints: BTreeSet::new(),
+            stop_listeners: Vec::new(),
+        };
+
+        (debugger, sender)
+    }
+
+    /// Returns a receiver notified every time the machine pauses
+    pub fn subscribe(&mut self) -> mpsc::Receiver<Stop> {
+        let (sender, receiver) = mpsc::channel();
+        self.stop_listeners.push(sender);
+        receiver
+    }
+
+    /// Tells the listeners the machine stopped, called by the runner once it halts or crashes
+    pub fn notify_stop(&mut self, stop: Stop) {
+        self.stop_listeners.retain(|l| l.send(stop).is_ok());
+    }
+
+    /// Handles pending commands, returns whether the machine should execute the next step
+    pub fn before_step(&mut self, machine: &mut Machine) -> bool {
+        let commands: Vec<_> = self.commands.try_iter().collect();
+        for command in commands {
+            match command {
+                Command::Pause => {
+                    if !matches!(self.state, State::Paused) {
+                        self.state = State::Paused;
+                        print_state(machine);
+                    }
+                    self.notify_stop(Stop::Paused);
+                },
+                Command::Continue => self.state = State::Running,
+                Command::Step => self.state = State::Step,
+                Command::StepOver => {
+                    self.state = match machine.current_instruction() {
+                        Some(Instruction::Call(_)) => State::StepOver {
+                            sp: machine.stack().len(),
+                        },
+                        _ => State::Step,
+                    }
+                },
+                Command::StepOut => {
+                    self.state = State::StepOut {
+                        sp: machine.stack().len(),
+                    }
+                },
+                Command::AddBreakpoint(a) => {
+                    self.breakpoints.insert(a);
+                },
+                Command::RemoveBreakpoint(a) => {
+                    self.breakpoints.remove(&a);
+                },
+                Command::ListBreakpoints => {
+                    for a in self.breakpoints.iter() {
+                        println!("0x{:03X}", a);
+                    }
+                },
+                Command::Inspect(f) => f(machine),
+            }
+        }
+
+        !matches!(self.state, State::Paused)
+    }
+
+    /// Pauses the machine if a breakpoint or a watchpoint was hit or the requested step is done
+    pub fn after_step(&mut self, machine: &mut Machine) {
+        let sp = machine.stack().len();
+
+        let watch_hits = machine.take_watch_hits();
+        for hit in watch_hits.iter() {
+            print!(
+                "Watchpoint: {} 0x{:03X} by 0x{:03X}: ",
+                hit.watchpoint.access, hit.address, hit.pc
+            );
+            match hit.instruction {
+                Some(instr) => println!("{}", instr),
+                None => println!("???"),
+            }
+        }
+
+        let done = match self.state {
+            State::Running => false,
+            State::Paused | State::Step => true,
+            State::StepOver { sp: target } => sp <= target,
+            State::StepOut { sp: target } => sp < target,
+        };
+
+        if done || !watch_hits.is_empty() || self.breakpoints.contains(&machine.pc()) {
+            if !done && watch_hits.is_empty() {
+                println!("Breakpoint at 0x{:03X}", machine.pc());
+            }
+            self.state = State::Paused;
+            print_state(machine);
+            self.notify_stop(Stop::Paused);
+        }
+    }
+}
+
+pub fn print_state(machine: &Machine) {
+    let registers = machine.registers();
+    let registers: Vec<_> = (0..16)
+        .map(|i| format!("V{:X}={:02X}", i, registers[i]))
+        .collect();
+    println!("{}", registers.join(" "));
+
+    print!(
+        "I=0x{:03X} DT={:02X} ST={:02X} SP={:X} stack:",
+        machine.i(),
+        machine.delay(),
+        machine.sound(),
+        machine.stack().len()
+    );
+    for addr in machine.stack() {
+        print!(" 0x{:03X}", addr);
+    }
+    println!();
+
+    print_disassembly(machine, machine.pc().saturating_sub(8), 10);
+}
+
+pub fn print_disassembly(machine: &Machine, start: usize, count: usize) {
+    let memory = machine.memory();
+
+    for addr in (start..).step_by(2).take(count) {
+        if addr + 1 >= memory.len() {
+            break;
+        }
+
+        let marker = if addr == machine.pc() { ">" } else { " " };
+        print!(
+            "{} 0x{:03X}: ({:02X}{:02X}) ",
+            marker,
+            addr,
+            memory[addr],
+            memory[addr + 1]
+        );
+        match Instruction::parse(memory[addr], memory[addr + 1]) {
+            Some(instr) => println!("{}", instr),
+            None => println!("???"),
+        }
+    }
+}
+
+const HELP: &str = "\
+c, continue         run until a breakpoint is hit
+p, pause            pause the machine
+s, step             execute one instruction
+n, next             execute one instruction, stepping over calls
+f, finish           run until the current procedure returns
+b, break <addr>     add a breakpoint
+d, delete <addr>    remove a breakpoint
+bl, breakpoints     list breakpoints
+w, watch <r|w|x> <addr>[-<end>]
+                    add a memory watchpoint
+dw, unwatch <addr>  remove the watchpoints starting at addr
+wl, watchpoints     list watchpoints
+r, regs             show registers, timers and stack
+l, list [addr]      show disassembly around the program counter or addr
+h, help             show this help";
+
+/// Reads debugger commands from stdin until it is closed
+pub fn spawn_repl(commands: mpsc::Sender<Command>) {
+    use std::io::BufRead;
+
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+
+            let mut words = line.split_whitespace();
+            let command = match words.next() {
+                Some(c) => c,
+                None => continue,
+            };
+            if command == "w" || command == "watch" {
+                match parse_watchpoint(words.next(), words.next()) {
+                    Some(watchpoint) => {
+                        let command =
+                            Command::Inspect(Box::new(move |m| m.add_watchpoint(watchpoint)));
+                        if commands.send(command).is_err() {
+                            break;
+                        }
+                    },
+                    None => println!("Usage: watch <r|w|x> <addr>[-<end>]"),
+                }
+                continue;
+            }
+
+            let addr = words.next().map(parse_addr);
+
+            let command = match (command, addr) {
+                ("c", None) | ("continue", None) => Command::Continue,
+                ("p", None) | ("pause", None) => Command::Pause,
+                ("s", None) | ("step", None) => Command::Step,
+                ("n", None) | ("next", None) => Command::StepOver,
+                ("f", None) | ("finish", None) => Command::StepOut,
+                ("b", Some(Some(a))) | ("break", Some(Some(a))) => Command::AddBreakpoint(a),
+                ("d", Some(Some(a))) | ("delete", Some(Some(a))) => Command::RemoveBreakpoint(a),
+                ("bl", None) | ("breakpoints", None) => Command::ListBreakpoints,
+                ("dw", Some(Some(a))) | ("unwatch", Some(Some(a))) => {
+                    Command::Inspect(Box::new(move |m| m.remove_watchpoints(a)))
+                },
+                ("wl", None) | ("watchpoints", None) => Command::Inspect(Box::new(|m| {
+                    for w in m.watchpoints() {
+                        println!("{} 0x{:03X}-0x{:03X}", w.access, w.start, w.end);
+                    }
+                })),
+                ("r", None) | ("regs", None) => Command::Inspect(Box::new(|m| print_state(m))),
+                ("l", None) | ("list", None) => Command::Inspect(Box::new(|m| {
+                    print_disassembly(m, m.pc().saturating_sub(8), 10)
+                })),
+                ("l", Some(Some(a))) | ("list", Some(Some(a))) => {
+                    Command::Inspect(Box::new(move |m| print_disassembly(m, a, 10)))
+                },
+                ("h", None) | ("help", None) => {
+                    println!("{}", HELP);
+                    continue;
+                },
+                _ => {
+                    println!("Unknown command, type 'help' for the list of commands");
+                    continue;
+                },
+            };
+
+            if commands.send(command).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Parses `r 0x300` or `w 0x300-0x30F`
+fn parse_watchpoint(access: Option<&str>, range: Option<&str>) -> Option<Watchpoint> {
+    let access = match access? {
+        "r" => Access::Read,
+        "w" => Access::Write,
+        "x" => Access::Execute,
+        _ => return None,
+    };
+
+    let range = range?;
+    let (start, end) = match range.find('-') {
+        Some(pos) => (parse_addr(&range[..pos])?, parse_addr(&range[pos + 1..])?),
+        None => (parse_addr(range)?, parse_addr(range)?),
+    };
+    if end < start {
+        return None;
+    }
+
+    Some(Watchpoint { access, start, end })
+}
+
+/// Parses `0x200`, `$200` or `512`
+pub fn parse_addr(s: &str) -> Option<usize> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix('$')) {
+        usize::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
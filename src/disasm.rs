@@ -0,0 +1,184 @@
+use crate::instruction::Instruction;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+const START: usize = 0x200;
+
+/// Width in bytes of every row of a sprite, 2 for 16x16 sprites
+type SpriteWidth = usize;
+
+struct Analysis {
+    /// Addresses of the first byte of every reachable instruction
+    code: BTreeSet<usize>,
+    labels: BTreeSet<usize>,
+    sprites: BTreeMap<usize, SpriteWidth>,
+}
+
+/// Follows the control flow from 0x200, tracking I along every path to find sprites
+fn analyze(rom: &[u8]) -> Analysis {
+    let end = START + rom.len();
+    let mut analysis = Analysis {
+        code: BTreeSet::new(),
+        labels: BTreeSet::new(),
+        sprites: BTreeMap::new(),
+    };
+
+    let mut queue = vec![(START, None)];
+    analysis.labels.insert(START);
+
+    while let Some((mut addr, mut i)) = queue.pop() {
+        while addr >= START && addr + 1 < end && !analysis.code.contains(&addr) {
+            let instr = match Instruction::parse(rom[addr - START], rom[addr + 1 - START]) {
+                Some(instr) => instr,
+                None => break,
+            };
+            analysis.code.insert(addr);
+
+            let next = addr + instr_len(rom, addr);
+            match instr {
+                Instruction::Jump(a) => {
+                    analysis.labels.insert(a);
+                    queue.push((a, i));
+                    break;
+                },
+                Instruction::JumpPlus(a) => {
+                    // Most likely a jump table
+                    analysis.labels.insert(a);
+                    queue.push((a, None));
+                    break;
+                },
+                Instruction::Call(a) => {
+                    analysis.labels.insert(a);
+                    queue.push((a, i));
+                    i = None;
+                },
+                Instruction::Return | Instruction::Exit => break,
+
+                Instruction::SkipEqByte(..)
+                | Instruction::SkipNeByte(..)
+                | Instruction::SkipEq(..)
+                | Instruction::SkipNe(..)
+                | Instruction::SkipPressed(..)
+                | Instruction::SkipNPressed(..) => {
+                    let skipped = next + instr_len(rom, next);
+                    queue.push((skipped, i));
+                },
+
+                Instruction::LoadI(a) => {
+                    analysis.labels.insert(a);
+                    i = Some(a);
+                },
+                Instruction::LongLoadI if next <= end => {
+                    let a = (rom[addr + 2 - START] as usize) << 8 | rom[addr + 3 - START] as usize;
+                    analysis.labels.insert(a);
+                    i = Some(a);
+                },
+                Instruction::AddToI(_)
+                | Instruction::SetSprite(_)
+                | Instruction::SetBigSprite(_)
+                | Instruction::StoreRegisters(_)
+                | Instruction::RestoreRegisters(_) => i = None,
+
+                Instruction::Draw(_, _, n) => {
+                    if let Some(i) = i {
+                        let (rows, width) = if n == 0 { (16, 2) } else { (n as usize, 1) };
+                        for row in 0..rows {
+                            analysis.sprites.entry(i + row * width).or_insert(width);
+                        }
+                    }
+                },
+                _ => {},
+            }
+
+            addr = next;
+        }
+    }
+
+    analysis
+}
+
+/// `F000 NNNN` is the only 4 byte instruction
+fn instr_len(rom: &[u8], addr: usize) -> usize {
+    match rom.get(addr - START..addr - START + 2) {
+        Some([0xF0, 0x00]) => 4,
+        _ => 2,
+    }
+}
+
+/// Produces source that assembles back into the same ROM, loaded at 0x200
+pub fn disassemble(rom: &[u8]) -> String {
+    let analysis = analyze(rom);
+    let end = START + rom.len();
+    let mut out = String::new();
+
+    let mut addr = START;
+    while addr < end {
+        if analysis.labels.contains(&addr) {
+            writeln!(out, "label_{:03X}:", addr).unwrap();
+        }
+
+        // Code that overlaps the next instruction is printed as data
+        let len = instr_len(rom, addr);
+        let is_code = analysis.code.contains(&addr)
+            && addr + len <= end
+            && (addr + 1..addr + len).all(|a| !analysis.code.contains(&a));
+
+        if is_code {
+            let (op1, op2) = (rom[addr - START], rom[addr + 1 - START]);
+            let instr = Instruction::parse(op1, op2).unwrap();
+            line(
+                &mut out,
+                &instr.to_string(),
+                addr,
+                &rom[addr - START..addr - START + 2],
+            );
+
+            if len == 4 {
+                let long = &rom[addr + 2 - START..addr + 4 - START];
+                let data = format!("dw   0x{:02X}{:02X}", long[0], long[1]);
+                line(&mut out, &data, addr + 2, long);
+            }
+
+            addr += len;
+        } else if let Some(width) = analysis.sprites.get(&addr) {
+            let row = &rom[addr - START..(addr + width).min(end) - START];
+            let bits: Vec<_> = row.iter().map(|b| format!("0b{:08b}", b)).collect();
+            let art: String = row
+                .iter()
+                .flat_map(|b| (0..8).rev().map(move |bit| b >> bit & 1 != 0))
+                .map(|pix| if pix { '#' } else { '.' })
+                .collect();
+            writeln!(
+                out,
+                "    {:<32}; {}",
+                format!("db   {}", bits.join(", ")),
+                art
+            )
+            .unwrap();
+
+            addr += row.len();
+        } else {
+            // Plain data up to the next label, instruction or sprite, 8 bytes a line
+            let data_end = (addr + 1..end)
+                .take(7)
+                .find(|a| {
+                    analysis.labels.contains(a)
+                        || analysis.code.contains(a)
+                        || analysis.sprites.contains_key(a)
+                })
+                .unwrap_or_else(|| (addr + 8).min(end));
+            let data = &rom[addr - START..data_end - START];
+            let bytes: Vec<_> = data.iter().map(|b| format!("0x{:02X}", b)).collect();
+            line(&mut out, &format!("db   {}", bytes.join(", ")), addr, data);
+
+            addr = data_end;
+        }
+    }
+
+    out
+}
+
+fn line(out: &mut String, text: &str, addr: usize, bytes: &[u8]) {
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    writeln!(out, "    {:<31} ; 0x{:03X}: {}", text, addr, hex).unwrap();
+}
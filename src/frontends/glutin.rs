@@ -1,3 +1,10 @@
+use super::{Event, Frontend};
+use crate::keymap::{Keymap, HOTKEYS};
+use crate::machine::MachineError;
+use crate::movie::Counter;
+use crate::screen::{HEIGHT, WIDTH};
+use crate::sound::{self, Speaker, Synth, Tone};
+
 const VERT_SRC: &str = concat!(
     r#"#version 330 core
     out vec2 f_pos;
@@ -22,13 +29,25 @@ const FRAG_SRC: &str = concat!(
 
     out vec4 o_color;
 
-    uniform bool screen[64 * 32];
+    uniform sampler2D screen;
+    uniform bool crashed;
+
+    // Colors for every combination of the two bitplanes
+    const vec4 palette[4] = vec4[](
+        vec4(0.0, 0.0, 0.0, 1.0),
+        vec4(1.0, 1.0, 1.0, 1.0),
+        vec4(0.33, 0.33, 0.33, 1.0),
+        vec4(0.67, 0.67, 0.67, 1.0)
+    );
 
     void main() {
-        if (screen[min(int(f_pos.y * 32.0), 31) * 64 + min(int(f_pos.x * 64.0), 63)]) {
-            o_color = vec4(1.0, 1.0, 1.0, 1.0);
-        } else {
-            o_color = vec4(0.0, 0.0, 0.0, 1.0);
+        ivec2 size = textureSize(screen, 0);
+        ivec2 pos = min(ivec2(f_pos * vec2(size)), size - 1);
+
+        o_color = palette[int(texelFetch(screen, pos, 0).r * 255.0 + 0.5) & 3];
+
+        if (crashed) {
+            o_color *= vec4(1.0, 0.25, 0.25, 1.0);
         }
     }"#,
     "\0",
@@ -39,20 +58,29 @@ pub struct GlutinWindow {
     windowed_context: glutin::WindowedContext<glutin::PossiblyCurrent>,
 
     program: u32,
-    program_screen: i32,
+    program_crashed: i32,
+    crashed: bool,
 
     dummy_vao: u32,
+    screen_texture: u32,
 
     gl: GlFunctions,
+
+    speaker: Option<Speaker>,
+    keymap: Keymap,
+    /// Parts of the title, the status is cleared when the speed changes
+    speed: String,
+    counter: String,
+    status: String,
 }
 
 impl GlutinWindow {
-    pub fn new() -> Self {
+    pub fn new(synth: Synth, keymap: Keymap) -> Self {
         let events_loop = glutin::EventsLoop::new();
         let wb = glutin::WindowBuilder::new()
             .with_title("CHIP8")
             .with_dimensions(glutin::dpi::LogicalSize::from_physical(
-                (16 * 64, 16 * 32),
+                (8 * WIDTH as u32, 8 * HEIGHT as u32),
                 events_loop.get_primary_monitor().get_hidpi_factor(),
             ));
         let windowed_context = glutin::ContextBuilder::new()
@@ -162,16 +190,38 @@ impl GlutinWindow {
         };
 
         let program_screen = unsafe { (gl.get_uniform_location)(program, "screen\0".as_ptr()) };
+        let program_crashed = unsafe { (gl.get_uniform_location)(program, "crashed\0".as_ptr()) };
 
         let mut dummy_vao = 0;
         unsafe {
             (gl.gen_vertex_arrays)(1, &mut dummy_vao);
         }
 
+        let mut screen_texture = 0;
+        unsafe {
+            (gl.gen_textures)(1, &mut screen_texture);
+            (gl.bind_texture)(0x0DE1, screen_texture); // TEXTURE_2D
+            (gl.tex_parameteri)(0x0DE1, 0x2801, 0x2600); // TEXTURE_MIN_FILTER, NEAREST
+            (gl.tex_parameteri)(0x0DE1, 0x2800, 0x2600); // TEXTURE_MAG_FILTER, NEAREST
+            (gl.pixel_storei)(0x0CF5, 1); // UNPACK_ALIGNMENT
+            (gl.tex_image_2d)(
+                0x0DE1,      // TEXTURE_2D
+                0,           //
+                0x8229,      // R8
+                WIDTH as _,  //
+                HEIGHT as _, //
+                0,           //
+                0x1903,      // RED
+                0x1401,      // UNSIGNED_BYTE
+                [[0u8; WIDTH]; HEIGHT].as_ptr() as _,
+            );
+        }
+
         unsafe {
             (gl.use_program)(program);
+            (gl.uniform_1i)(program_screen, 0);
             (gl.bind_vertex_array)(dummy_vao);
-            (gl.viewport)(0, 0, 16 * 64, 16 * 32);
+            (gl.viewport)(0, 0, 8 * WIDTH as isize, 8 * HEIGHT as isize);
         }
 
         Self {
@@ -179,120 +229,182 @@ impl GlutinWindow {
             windowed_context,
 
             program,
-            program_screen,
+            program_crashed,
+            crashed: false,
 
             dummy_vao,
+            screen_texture,
 
             gl,
+
+            speaker: Speaker::open(synth),
+            keymap,
+            speed: String::new(),
+            counter: String::new(),
+            status: String::new(),
         }
     }
 
-    pub fn run(&mut self, freq: u8, mut machine: crate::machine::Machine) {
-        use std::sync::{
-            atomic::{AtomicBool, Ordering},
-            Arc, Mutex,
-        };
+    /// `CHIP8 - <speed> - <movie counter> - <status>`
+    fn update_title(&self) {
+        if self.crashed {
+            return;
+        }
 
-        // 1 2 3 C -> 02 03 04 05 (1 2 3 4)
-        // 4 5 6 D -> 16 17 18 19 (Q W E R)
-        // 7 8 9 E -> 30 31 32 33 (A S D F)
-        // A 0 B F -> 44 45 46 47 (Z X C V)
-
-        let keyboard_settings = [45, 2, 3, 4, 16, 17, 18, 30, 31, 32, 44, 46, 5, 19, 33, 47];
-
-        let keyboard = Arc::new(Mutex::new([false; 16]));
-        let running = Arc::new(AtomicBool::new(true));
-        let screen = Arc::new(Mutex::new([[0u32; 64]; 32]));
-        let needs_redraw = Arc::new(AtomicBool::new(false));
-
-        let machine_thread = {
-            let keyboard = keyboard.clone();
-            let running = running.clone();
-            let screen = screen.clone();
-            let needs_redraw = needs_redraw.clone();
-
-            std::thread::spawn(move || {
-                let mut loop_helper = spin_sleep::LoopHelper::builder()
-                    .build_with_target_rate(60.0 * f64::from(freq));
-
-                while running.load(Ordering::SeqCst) {
-                    loop_helper.loop_start();
-
-                    machine.step(*keyboard.lock().unwrap());
-                    if machine.screen().needs_redraw() {
-                        needs_redraw.store(true, Ordering::SeqCst);
-
-                        let mut screen_lock = screen.lock().unwrap();
-                        let machine_screen = machine.screen().buffer();
-                        for y in 0..32 {
-                            for x in 0..64 {
-                                screen_lock[y][x] = machine_screen[y][x] as _;
-                            }
-                        }
+        let mut title = "CHIP8".to_string();
+        for part in [&self.speed, &self.counter, &self.status].iter() {
+            if !part.is_empty() {
+                title = title + " - " + part;
+            }
+        }
+        self.windowed_context.window().set_title(&title);
+    }
 
-                        machine.screen().redrawn();
-                    }
+    fn toggle_mute(&mut self) {
+        if let Some(speaker) = &mut self.speaker {
+            let muted = speaker.toggle_mute();
+            self.set_status(sound::mute_status(muted));
+        }
+    }
 
-                    loop_helper.loop_sleep();
-                }
-            })
-        };
+    fn change_volume(&mut self, delta: f64) {
+        if let Some(speaker) = &mut self.speaker {
+            let volume = speaker.change_volume(delta);
+            self.set_status(&sound::volume_status(volume));
+        }
+    }
 
-        let mut loop_helper = spin_sleep::LoopHelper::builder().build_with_target_rate(60.0);
-
-        while running.load(Ordering::SeqCst) {
-            loop_helper.loop_start();
-
-            let mut events = Vec::new();
-            self.events_loop.poll_events(|e| events.push(e));
-
-            for event in events {
-                if let glutin::Event::WindowEvent { event, .. } = event {
-                    use glutin::WindowEvent::*;
-                    match event {
-                        CloseRequested | Destroyed => running.store(false, Ordering::SeqCst),
-                        Resized(lsize) => {
-                            // Set glviewport
-                            let size = lsize
-                                .to_physical(self.windowed_context.window().get_hidpi_factor());
-                            unsafe {
-                                (self.gl.viewport)(0, 0, size.width as _, size.height as _);
-                            }
-                        },
-                        KeyboardInput { input, .. } => {
-                            for (i, key) in keyboard_settings.iter().enumerate() {
-                                if input.scancode == *key {
-                                    (*keyboard.lock().unwrap())[i] =
-                                        input.state == glutin::ElementState::Pressed;
-                                }
-                            }
-                        },
-                        _ => {},
-                    }
-                }
-            }
+    fn draw(&mut self) {
+        unsafe {
+            (self.gl.draw_arrays)(0x0005, 0, 4); // TRIANGLE_STRIP
+        }
 
-            if needs_redraw.load(Ordering::SeqCst) {
-                unsafe {
-                    (self.gl.uniform_1iv)(
-                        self.program_screen,
-                        64 * 32,
-                        screen.lock().unwrap().as_ptr() as _,
-                    );
-                }
-                needs_redraw.store(false, Ordering::SeqCst);
-            }
+        self.windowed_context.swap_buffers().unwrap();
+    }
+}
+
+/// Name of the key in keymaps, `Key1` is `1`, `Numpad1` is `numpad1`
+fn key_name(key: glutin::VirtualKeyCode) -> String {
+    let name = format!("{:?}", key).to_lowercase();
+    match name.strip_prefix("key") {
+        Some(digit) if digit.len() == 1 => digit.to_string(),
+        _ => name,
+    }
+}
+
+impl Frontend for GlutinWindow {
+    fn present(&mut self, buffer: &[[u8; WIDTH]; HEIGHT], _hires: bool) {
+        unsafe {
+            (self.gl.tex_sub_image_2d)(
+                0x0DE1,      // TEXTURE_2D
+                0,           //
+                0,           //
+                0,           //
+                WIDTH as _,  //
+                HEIGHT as _, //
+                0x1903,      // RED
+                0x1401,      // UNSIGNED_BYTE
+                buffer.as_ptr() as _,
+            );
+        }
 
-            unsafe {
-                (self.gl.draw_arrays)(0x0005, 0, 4); // TRIANGLE_STRIP
+        self.draw();
+    }
+
+    fn poll_input(&mut self, _frame: u64, keys: &mut [bool; 16]) -> Vec<Event> {
+        let mut window_events = Vec::new();
+        self.events_loop.poll_events(|e| window_events.push(e));
+
+        let mut events = Vec::new();
+        for event in window_events {
+            if let glutin::Event::WindowEvent { event, .. } = event {
+                use glutin::WindowEvent::*;
+                match event {
+                    CloseRequested | Destroyed => events.push(Event::Quit),
+                    Resized(lsize) => {
+                        // Set glviewport
+                        let size =
+                            lsize.to_physical(self.windowed_context.window().get_hidpi_factor());
+                        unsafe {
+                            (self.gl.viewport)(0, 0, size.width as _, size.height as _);
+                        }
+                        self.draw();
+                    },
+                    KeyboardInput { input, .. } => {
+                        let pressed = input.state == glutin::ElementState::Pressed;
+
+                        use glutin::VirtualKeyCode::*;
+                        match input.virtual_keycode {
+                            Some(Back) => events.push(Event::Rewind(pressed)),
+                            Some(F5) if pressed => events.push(Event::SaveState),
+                            Some(F9) if pressed => events.push(Event::LoadState),
+                            Some(F6) if pressed => events.push(Event::PreviousSlot),
+                            Some(F7) if pressed => events.push(Event::NextSlot),
+                            Some(F12) if pressed => events.push(Event::Screenshot),
+                            Some(M) if pressed => self.toggle_mute(),
+                            Some(Minus) if pressed => self.change_volume(-0.1),
+                            Some(Equals) if pressed => self.change_volume(0.1),
+                            Some(P) if pressed => events.push(Event::Pause),
+                            Some(N) if pressed => events.push(Event::AdvanceFrame),
+                            Some(I) if pressed => events.push(Event::StepInstruction),
+                            Some(LBracket) if pressed => events.push(Event::Slower),
+                            Some(RBracket) if pressed => events.push(Event::Faster),
+                            Some(T) if pressed => events.push(Event::ToggleReadOnly),
+                            _ => {},
+                        }
+
+                        let name = input.virtual_keycode.map(key_name);
+                        if name.as_deref().is_some_and(|name| HOTKEYS.contains(&name)) {
+                            continue;
+                        }
+                        for i in self.keymap.find(name.as_deref(), Some(input.scancode)) {
+                            keys[i] = pressed;
+                        }
+                    },
+                    _ => {},
+                }
             }
+        }
 
-            self.windowed_context.swap_buffers().unwrap();
+        events
+    }
 
-            loop_helper.loop_sleep();
+    fn play_tone(&mut self, tone: Tone) {
+        if let Some(speaker) = &mut self.speaker {
+            speaker.set_tone(Some(tone));
         }
+    }
+    fn stop_tone(&mut self) {
+        if let Some(speaker) = &mut self.speaker {
+            speaker.set_tone(None);
+        }
+    }
+
+    /// Shown in the title
+    fn set_status(&mut self, status: &str) {
+        self.status = status.to_string();
+        self.update_title();
+    }
+
+    fn set_speed(&mut self, ipf: Option<u32>, paused: bool) {
+        self.speed = super::speed(ipf, paused);
+        self.status.clear();
+        self.update_title();
+    }
 
-        machine_thread.join().unwrap();
+    fn set_counter(&mut self, counter: Option<&Counter>) {
+        self.counter = counter.map(ToString::to_string).unwrap_or_default();
+        self.update_title();
+    }
+
+    /// Tints the screen red
+    fn crashed(&mut self, error: &MachineError) {
+        self.set_status(&format!("crashed: {}", error));
+        self.crashed = true;
+        unsafe {
+            (self.gl.uniform_1i)(self.program_crashed, 1);
+        }
+        self.draw();
     }
 }
 
@@ -301,6 +413,7 @@ impl Drop for GlutinWindow {
         unsafe {
             (self.gl.delete_program)(self.program);
             (self.gl.delete_vertex_arrays)(1, &self.dummy_vao);
+            (self.gl.delete_textures)(1, &self.screen_texture);
         }
     }
 }
@@ -326,13 +439,24 @@ struct GlFunctions {
     delete_vertex_arrays: unsafe extern "C" fn(isize, *const u32),
     use_program: unsafe extern "C" fn(u32),
     get_uniform_location: unsafe extern "C" fn(u32, *const u8) -> i32,
-    uniform_1iv: unsafe extern "C" fn(i32, isize, *const i32),
+    uniform_1i: unsafe extern "C" fn(i32, i32),
+
+    gen_textures: unsafe extern "C" fn(isize, *mut u32),
+    bind_texture: unsafe extern "C" fn(u32, u32),
+    delete_textures: unsafe extern "C" fn(isize, *const u32),
+    tex_parameteri: unsafe extern "C" fn(u32, u32, i32),
+    pixel_storei: unsafe extern "C" fn(u32, i32),
+    tex_image_2d:
+        unsafe extern "C" fn(u32, i32, i32, isize, isize, i32, u32, u32, *const std::ffi::c_void),
+    tex_sub_image_2d:
+        unsafe extern "C" fn(u32, i32, i32, i32, isize, isize, u32, u32, *const std::ffi::c_void),
 
     viewport: unsafe extern "C" fn(i32, i32, isize, isize),
     draw_arrays: unsafe extern "C" fn(u32, i32, isize),
 }
 
 impl GlFunctions {
+    #[allow(clippy::missing_transmute_annotations)]
     fn new(loader: impl Fn(&str) -> *const ()) -> Self {
         Self {
             create_shader: unsafe { std::mem::transmute(loader("glCreateShader")) },
@@ -355,7 +479,15 @@ impl GlFunctions {
             delete_vertex_arrays: unsafe { std::mem::transmute(loader("glDeleteVertexArrays")) },
             use_program: unsafe { std::mem::transmute(loader("glUseProgram")) },
             get_uniform_location: unsafe { std::mem::transmute(loader("glGetUniformLocation")) },
-            uniform_1iv: unsafe { std::mem::transmute(loader("glUniform1iv")) },
+            uniform_1i: unsafe { std::mem::transmute(loader("glUniform1i")) },
+
+            gen_textures: unsafe { std::mem::transmute(loader("glGenTextures")) },
+            bind_texture: unsafe { std::mem::transmute(loader("glBindTexture")) },
+            delete_textures: unsafe { std::mem::transmute(loader("glDeleteTextures")) },
+            tex_parameteri: unsafe { std::mem::transmute(loader("glTexParameteri")) },
+            pixel_storei: unsafe { std::mem::transmute(loader("glPixelStorei")) },
+            tex_image_2d: unsafe { std::mem::transmute(loader("glTexImage2D")) },
+            tex_sub_image_2d: unsafe { std::mem::transmute(loader("glTexSubImage2D")) },
 
             viewport: unsafe { std::mem::transmute(loader("glViewport")) },
             draw_arrays: unsafe { std::mem::transmute(loader("glDrawArrays")) },
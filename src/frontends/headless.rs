@@ -0,0 +1,234 @@
+//! Runs a machine without any window, for automated tests
+//!
+//! Input scripts have one `<frame> <keys>` line per change of the pressed keys, where keys are
+//! hex digits held from that frame on, or `-` for none:
+//!
+//! ```text
+//! # press 5 on the 60th frame, release it 10 frames later
+//! 60 5
+//! 70 -
+//! 90 4A
+//! ```
+
+use super::{Event, Frontend};
+use crate::machine::MachineError;
+use crate::screen::{HEIGHT, WIDTH};
+use crate::sound::{Synth, Tone, SAMPLE_RATE};
+
+pub struct Headless {
+    /// Keys pressed from the frame on, sorted by frame
+    pub input: Vec<(u64, [bool; 16])>,
+    next_input: usize,
+    crashed: bool,
+    /// Renders the sound of every frame into `samples` when set
+    pub synth: Option<Synth>,
+    pub samples: Vec<f32>,
+    /// Frames already rendered
+    rendered: u64,
+}
+
+impl Headless {
+    pub fn new() -> Self {
+        Self {
+            input: Vec::new(),
+            next_input: 0,
+            crashed: false,
+            synth: None,
+            samples: Vec::new(),
+            rendered: 0,
+        }
+    }
+
+    /// Parses an input script, returns the line number of the first invalid line on error
+    pub fn parse_input(script: &str) -> Result<Vec<(u64, [bool; 16])>, usize> {
+        let mut input: Vec<(u64, [bool; 16])> = Vec::new();
+
+        for (n, line) in script.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let frame = words.next().and_then(|f| f.parse().ok()).ok_or(n + 1)?;
+            let mut keys = [false; 16];
+            match words.next() {
+                Some("-") => {},
+                Some(digits) => {
+                    for digit in digits.chars() {
+                        keys[digit.to_digit(16).ok_or(n + 1)? as usize] = true;
+                    }
+                },
+                None => return Err(n + 1),
+            }
+            if words.next().is_some() || input.last().is_some_and(|(f, _)| *f > frame) {
+                return Err(n + 1);
+            }
+
+            input.push((frame, keys));
+        }
+
+        Ok(input)
+    }
+}
+
+impl Frontend for Headless {
+    /// The screen is dumped once the run is over
+    fn present(&mut self, _buffer: &[[u8; WIDTH]; HEIGHT], _hires: bool) {}
+
+    fn poll_input(&mut self, frame: u64, keys: &mut [bool; 16]) -> Vec<Event> {
+        if self.crashed {
+            return vec![Event::Quit];
+        }
+
+        while let Some((_, pressed)) = self.input[self.next_input..]
+            .first()
+            .filter(|(f, _)| *f <= frame)
+        {
+            *keys = *pressed;
+            self.next_input += 1;
+        }
+
+        // The tone set before this frame started plays for the whole frame
+        if let Some(synth) = &mut self.synth {
+            while self.rendered <= frame {
+                let start = self.samples.len();
+                let end = ((self.rendered + 1) * u64::from(SAMPLE_RATE) / 60) as usize;
+                self.samples.resize(end, 0.0);
+                synth.fill(SAMPLE_RATE, &mut self.samples[start..]);
+                self.rendered += 1;
+            }
+        }
+
+        Vec::new()
+    }
+
+    fn play_tone(&mut self, tone: Tone) {
+        if let Some(synth) = &mut self.synth {
+            synth.tone = Some(tone);
+        }
+    }
+    fn stop_tone(&mut self) {
+        if let Some(synth) = &mut self.synth {
+            synth.tone = None;
+        }
+    }
+
+    fn crashed(&mut self, _error: &MachineError) {
+        self.crashed = true;
+    }
+}
+
+/// One line per row, `.` for empty pixels and `#`, `+`, `*` for planes 1, 2 and both, 64x32
+/// unless `hires`
+pub fn dump(buffer: &[[u8; WIDTH]; HEIGHT], hires: bool) -> String {
+    // Low-res pixels are 2x2 in the buffer
+    let scale = if hires { 1 } else { 2 };
+    let mut out = String::with_capacity((WIDTH / scale + 1) * HEIGHT / scale);
+    for row in buffer.iter().step_by(scale) {
+        out.extend(row.iter().step_by(scale).map(|p| match p & 3 {
+            0 => '.',
+            1 => '#',
+            2 => '+',
+            _ => '*',
+        }));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(digits: &[usize]) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for digit in digits {
+            keys[*digit] = true;
+        }
+        keys
+    }
+
+    #[test]
+    fn input() {
+        let script = "# comment\n\n60 5\n70 -  # released\n70 4a\n  90 F0 \n";
+        let input = Headless::parse_input(script).unwrap();
+        assert_eq!(
+            input,
+            [
+                (60, keys(&[5])),
+                (70, keys(&[])),
+                (70, keys(&[4, 0xA])),
+                (90, keys(&[0, 0xF])),
+            ]
+        );
+        assert_eq!(Headless::parse_input(""), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn input_errors() {
+        assert_eq!(Headless::parse_input("60"), Err(1));
+        assert_eq!(Headless::parse_input("1 5\nx 5"), Err(2));
+        assert_eq!(Headless::parse_input("-1 5"), Err(1));
+        assert_eq!(Headless::parse_input("1 5G"), Err(1));
+        assert_eq!(Headless::parse_input("1 5 6"), Err(1));
+        // Frames go forward
+        assert_eq!(Headless::parse_input("# first\n10 1\n\n5 2"), Err(4));
+    }
+
+    #[test]
+    fn input_is_held_from_its_frame() {
+        let mut headless = Headless::new();
+        headless.input = Headless::parse_input("2 1\n2 3\n4 -\n5 7").unwrap();
+        let mut held = [false; 16];
+        let pressed: Vec<_> = (0..7)
+            .map(|frame| {
+                headless.poll_input(frame, &mut held);
+                held
+            })
+            .collect();
+        assert_eq!(
+            pressed,
+            [
+                keys(&[]),
+                keys(&[]),
+                keys(&[3]),
+                keys(&[3]),
+                keys(&[]),
+                keys(&[7]),
+                keys(&[7]),
+            ]
+        );
+    }
+
+    #[test]
+    fn lowres_dump() {
+        let mut buffer = [[0; WIDTH]; HEIGHT];
+        // A 2x2 low-res pixel for every plane combination
+        for (x, plane) in [1, 2, 3].iter().enumerate() {
+            for row in &mut buffer[2..4] {
+                row[x * 2..x * 2 + 2].copy_from_slice(&[*plane; 2]);
+            }
+        }
+        let dump = dump(&buffer, false);
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert!(lines.iter().all(|l| l.len() == 64));
+        assert_eq!(&lines[1][..4], "#+*.");
+        assert!(lines[0].chars().all(|c| c == '.'));
+    }
+
+    #[test]
+    fn hires_dump() {
+        let mut buffer = [[0; WIDTH]; HEIGHT];
+        buffer[63][127] = 1;
+        buffer[0][1] = 2;
+        let dump = dump(&buffer, true);
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(lines.len(), 64);
+        assert!(lines.iter().all(|l| l.len() == 128));
+        assert_eq!(&lines[0][..3], ".+.");
+        assert!(lines[63].ends_with(".#"));
+        assert_eq!(dump.len(), 129 * 64);
+    }
+}
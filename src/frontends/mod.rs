@@ -1,3 +1,78 @@
 pub mod glutin;
+pub mod headless;
+pub mod terminal;
 
 pub use self::glutin::GlutinWindow;
+pub use self::headless::Headless;
+pub use self::terminal::Terminal;
+
+use crate::machine::MachineError;
+use crate::movie::Counter;
+use crate::screen::{HEIGHT, WIDTH};
+use crate::sound::Tone;
+
+pub const NAMES: [&str; 3] = ["gl", "terminal", "headless"];
+
+/// Input handled by the runner rather than the machine
+pub enum Event {
+    SaveState,
+    LoadState,
+    PreviousSlot,
+    NextSlot,
+    /// Whether the rewind key is held
+    Rewind(bool),
+    Pause,
+    /// Runs one 60hz frame while paused
+    AdvanceFrame,
+    /// Runs one instruction while paused
+    StepInstruction,
+    Faster,
+    Slower,
+    /// Switches the movie between read-only and read-write
+    ToggleReadOnly,
+    Screenshot,
+    Quit,
+}
+
+/// Output and input of the machine, driven by `runner::Runner` once per 60hz frame
+pub trait Frontend {
+    /// Shows the screen, called only when it changed
+    fn present(&mut self, buffer: &[[u8; WIDTH]; HEIGHT], hires: bool);
+
+    /// Handles pending input before the frame `frame`, updating the pressed keypad keys
+    fn poll_input(&mut self, frame: u64, keys: &mut [bool; 16]) -> Vec<Event>;
+
+    /// Called when the tone changes, until `stop_tone`
+    fn play_tone(&mut self, _tone: Tone) {}
+    fn stop_tone(&mut self) {}
+
+    /// Shows a short message, like the selected save slot
+    fn set_status(&mut self, _status: &str) {}
+
+    /// Called at the start and whenever the speed or pause state changes, `ipf` is `None` with
+    /// VIP timing
+    fn set_speed(&mut self, ipf: Option<u32>, paused: bool) {
+        self.set_status(&speed(ipf, paused));
+    }
+
+    /// Called whenever the frame counter of a movie changes, `None` without a movie
+    fn set_counter(&mut self, _counter: Option<&Counter>) {}
+
+    /// Called once the machine crashed, input is polled until `Event::Quit`
+    fn crashed(&mut self, error: &MachineError) {
+        self.set_status(&format!("crashed: {}", error));
+    }
+}
+
+/// `10 ipf` or `10 ipf, paused` in instructions per frame, `vip` with VIP timing
+pub fn speed(ipf: Option<u32>, paused: bool) -> String {
+    let speed = match ipf {
+        Some(ipf) => format!("{} ipf", ipf),
+        None => "vip".to_string(),
+    };
+    if paused {
+        speed + ", paused"
+    } else {
+        speed
+    }
+}
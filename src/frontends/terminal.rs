@@ -0,0 +1,221 @@
+use super::{Event, Frontend};
+use crate::keymap::Keymap;
+use crate::machine::MachineError;
+use crate::movie::Counter;
+use crate::screen::{HEIGHT, WIDTH};
+use crate::sound::{self, Speaker, Synth, Tone};
+use std::fmt::Write as _;
+use std::io::Write;
+use termion::color::{Bg, Fg, Rgb};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::IntoAlternateScreen;
+
+/// Terminals only report key presses, so a key stays pressed for this many frames after the last
+/// press or autorepeat
+const HOLD_FRAMES: u8 = 10;
+
+/// Same colors as the GL shader
+const PALETTE: [Rgb; 4] = [
+    Rgb(0, 0, 0),
+    Rgb(255, 255, 255),
+    Rgb(85, 85, 85),
+    Rgb(170, 170, 170),
+];
+
+type Output = termion::screen::AlternateScreen<termion::raw::RawTerminal<std::io::Stdout>>;
+
+pub struct Terminal {
+    out: Output,
+    input: termion::input::Keys<termion::AsyncReader>,
+    /// Frames left before every key is released
+    held: [u8; 16],
+    frame: u64,
+    /// Rendered rows currently on the terminal
+    rows: Vec<String>,
+    speaker: Option<Speaker>,
+    keymap: Keymap,
+}
+
+impl Terminal {
+    pub fn new(synth: Synth, keymap: Keymap) -> std::io::Result<Self> {
+        let mut out = std::io::stdout().into_raw_mode()?.into_alternate_screen()?;
+        write!(out, "{}{}", termion::cursor::Hide, termion::clear::All)?;
+
+        Ok(Self {
+            out,
+            input: termion::async_stdin().keys(),
+            held: [0; 16],
+            frame: 0,
+            rows: Vec::new(),
+            speaker: Speaker::open(synth),
+            keymap,
+        })
+    }
+}
+
+/// Name of the key in keymaps, as in the window
+fn key_name(key: Key) -> Option<String> {
+    Some(match key {
+        Key::Char(' ') => "space".to_string(),
+        Key::Char('\n') => "return".to_string(),
+        Key::Char('\t') => "tab".to_string(),
+        Key::Char(c) => c.to_lowercase().to_string(),
+        Key::Up => "up".to_string(),
+        Key::Down => "down".to_string(),
+        Key::Left => "left".to_string(),
+        Key::Right => "right".to_string(),
+        Key::Home => "home".to_string(),
+        Key::End => "end".to_string(),
+        Key::PageUp => "pageup".to_string(),
+        Key::PageDown => "pagedown".to_string(),
+        Key::Insert => "insert".to_string(),
+        Key::Delete => "delete".to_string(),
+        Key::Backspace => "back".to_string(),
+        _ => return None,
+    })
+}
+
+impl Frontend for Terminal {
+    /// Repaints the rows that changed, two pixels per character
+    fn present(&mut self, buffer: &[[u8; WIDTH]; HEIGHT], hires: bool) {
+        let scale = if hires { 1 } else { 2 };
+        let (width, height) = (WIDTH / scale, HEIGHT / scale);
+
+        if self.rows.len() != height / 2 {
+            self.rows = vec![String::new(); height / 2];
+            write!(self.out, "{}", termion::clear::All).ok();
+        }
+
+        for (y, old) in self.rows.iter_mut().enumerate() {
+            let upper = &buffer[y * 2 * scale];
+            let lower = &buffer[(y * 2 + 1) * scale];
+
+            let mut row = String::new();
+            let mut colors = None;
+            for x in (0..width).map(|x| x * scale) {
+                let pair = (upper[x] as usize & 3, lower[x] as usize & 3);
+                if colors != Some(pair) {
+                    write!(row, "{}{}", Fg(PALETTE[pair.0]), Bg(PALETTE[pair.1])).unwrap();
+                    colors = Some(pair);
+                }
+                row.push('▀');
+            }
+
+            if *old != row {
+                write!(
+                    self.out,
+                    "{}{}{}{}",
+                    termion::cursor::Goto(1, y as u16 + 1),
+                    row,
+                    termion::color::Reset.fg_str(),
+                    termion::color::Reset.bg_str()
+                )
+                .ok();
+                *old = row;
+            }
+        }
+
+        self.out.flush().ok();
+    }
+
+    fn poll_input(&mut self, frame: u64, keys: &mut [bool; 16]) -> Vec<Event> {
+        if frame != self.frame {
+            self.frame = frame;
+            for t in self.held.iter_mut() {
+                *t = t.saturating_sub(1);
+            }
+        }
+
+        let mut events = Vec::new();
+        while let Some(Ok(key)) = self.input.next() {
+            match key {
+                Key::Esc | Key::Ctrl('c') => events.push(Event::Quit),
+                Key::F(5) => events.push(Event::SaveState),
+                Key::F(9) => events.push(Event::LoadState),
+                Key::F(6) => events.push(Event::PreviousSlot),
+                Key::F(7) => events.push(Event::NextSlot),
+                Key::F(12) => events.push(Event::Screenshot),
+                Key::Char('m') => {
+                    if let Some(speaker) = &mut self.speaker {
+                        let muted = speaker.toggle_mute();
+                        self.set_status(sound::mute_status(muted));
+                    }
+                },
+                Key::Char('p') => events.push(Event::Pause),
+                Key::Char('n') => events.push(Event::AdvanceFrame),
+                Key::Char('i') => events.push(Event::StepInstruction),
+                Key::Char('[') => events.push(Event::Slower),
+                Key::Char(']') => events.push(Event::Faster),
+                Key::Char('t') => events.push(Event::ToggleReadOnly),
+                Key::Char(c @ '-') | Key::Char(c @ '=') => {
+                    if let Some(speaker) = &mut self.speaker {
+                        let volume = speaker.change_volume(if c == '-' { -0.1 } else { 0.1 });
+                        self.set_status(&sound::volume_status(volume));
+                    }
+                },
+                key => {
+                    let name = key_name(key);
+                    for i in self.keymap.find(name.as_deref(), None) {
+                        self.held[i] = HOLD_FRAMES;
+                    }
+                },
+            }
+        }
+
+        for (key, t) in keys.iter_mut().zip(self.held.iter()) {
+            *key = *t > 0;
+        }
+
+        events
+    }
+
+    fn play_tone(&mut self, tone: Tone) {
+        if let Some(speaker) = &mut self.speaker {
+            speaker.set_tone(Some(tone));
+        }
+    }
+    fn stop_tone(&mut self) {
+        if let Some(speaker) = &mut self.speaker {
+            speaker.set_tone(None);
+        }
+    }
+
+    /// Shown under the screen
+    fn set_status(&mut self, status: &str) {
+        write!(
+            self.out,
+            "{}{}{}",
+            termion::cursor::Goto(1, self.rows.len() as u16 + 1),
+            termion::clear::CurrentLine,
+            status
+        )
+        .ok();
+        self.out.flush().ok();
+    }
+
+    /// Shown under the status
+    fn set_counter(&mut self, counter: Option<&Counter>) {
+        write!(
+            self.out,
+            "{}{}{}",
+            termion::cursor::Goto(1, self.rows.len() as u16 + 2),
+            termion::clear::CurrentLine,
+            counter.map(ToString::to_string).unwrap_or_default()
+        )
+        .ok();
+        self.out.flush().ok();
+    }
+
+    fn crashed(&mut self, error: &MachineError) {
+        self.set_status(&format!("crashed: {} (Esc to quit)", error));
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        write!(self.out, "{}", termion::cursor::Show).ok();
+        self.out.flush().ok();
+    }
+}
@@ -0,0 +1,566 @@
+//! GDB remote serial protocol stub
+//!
+//! Registers are numbered V0-VF (0-15, 1 byte), I (16, 2 bytes), PC (17, 2 bytes),
+//! SP (18, 1 byte), DT (19, 1 byte), ST (20, 1 byte), all little-endian.
+
+use crate::debugger::{Command, Stop};
+use crate::machine::{Access, Machine, Watchpoint};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+
+const REGISTER_SIZES: [usize; 21] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 1, 1, 1,
+];
+
+enum Event {
+    Packet(String),
+    Interrupt,
+    Disconnected,
+    Stop(Stop),
+}
+
+/// Listens on localhost:`port` and serves one client at a time
+pub fn spawn(
+    port: u16,
+    commands: mpsc::Sender<Command>,
+    stops: mpsc::Receiver<Stop>,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (events, events_recv) = mpsc::channel();
+
+    {
+        let events = events.clone();
+        std::thread::spawn(move || {
+            for stop in stops.iter() {
+                if events.send(Event::Stop(stop)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            let reader = match stream.try_clone() {
+                Ok(reader) => reader,
+                Err(_) => continue,
+            };
+            let events = events.clone();
+            std::thread::spawn(move || read_packets(reader, events));
+
+            let mut session = Session {
+                stream,
+                commands: &commands,
+                waiting_for_stop: false,
+            };
+            if session.run(&events_recv).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn read_packets(mut stream: TcpStream, events: mpsc::Sender<Event>) {
+    let mut packet: Option<String> = None;
+    let mut checksum: Option<String> = None;
+    let mut byte = [0];
+
+    while let Ok(1) = stream.read(&mut byte) {
+        let c = byte[0] as char;
+
+        if let Some(digits) = &mut checksum {
+            digits.push(c);
+            if digits.len() == 2 {
+                let data = packet.take().unwrap();
+                let sum = data.chars().fold(0u8, |sum, c| sum.wrapping_add(c as u8));
+                let valid = u8::from_str_radix(digits, 16) == Ok(sum);
+                checksum = None;
+
+                // A bad packet is sent again by gdb after a NAK
+                if !valid {
+                    stream.write_all(b"-").ok();
+                    continue;
+                }
+                stream.write_all(b"+").ok();
+                if events.send(Event::Packet(data)).is_err() {
+                    return;
+                }
+            }
+            continue;
+        }
+
+        match (&mut packet, c) {
+            (None, '\x03') => {
+                if events.send(Event::Interrupt).is_err() {
+                    return;
+                }
+            },
+            (None, '$') => packet = Some(String::new()),
+            (None, _) => {}, // acks
+            (Some(_), '#') => checksum = Some(String::new()),
+            (Some(p), c) => p.push(c),
+        }
+    }
+
+    events.send(Event::Disconnected).ok();
+}
+
+struct Session<'a> {
+    stream: TcpStream,
+    commands: &'a mpsc::Sender<Command>,
+    waiting_for_stop: bool,
+}
+
+impl<'a> Session<'a> {
+    /// Returns Err once the machine thread is gone
+    fn run(&mut self, events: &mpsc::Receiver<Event>) -> Result<(), ()> {
+        for event in events.iter() {
+            match event {
+                Event::Packet(packet) => {
+                    if let Some(reply) = self.handle(&packet)? {
+                        self.send(&reply);
+                    }
+                    if packet == "k" {
+                        return Ok(());
+                    }
+                },
+                Event::Interrupt => {
+                    if self.waiting_for_stop {
+                        self.commands.send(Command::Pause).map_err(|_| ())?;
+                    }
+                },
+                Event::Stop(stop) => {
+                    if !self.waiting_for_stop {
+                        continue;
+                    }
+                    self.waiting_for_stop = false;
+                    match stop {
+                        Stop::Paused => self.send("S05"),
+                        Stop::Halted => {
+                            self.send("W00");
+                            return Err(());
+                        },
+                        // SIGSEGV
+                        Stop::Crashed => {
+                            self.send("X0b");
+                            return Err(());
+                        },
+                    }
+                },
+                Event::Disconnected => return Ok(()),
+            }
+        }
+
+        Err(())
+    }
+
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |a, b| a.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, checksum).ok();
+    }
+
+    /// Runs `f` on the machine thread and waits for its result
+    fn inspect<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Machine) -> T + Send + 'static,
+    ) -> Result<T, ()> {
+        let (sender, receiver) = mpsc::channel();
+        self.commands
+            .send(Command::Inspect(Box::new(move |m| {
+                sender.send(f(m)).ok();
+            })))
+            .map_err(|_| ())?;
+        receiver.recv().map_err(|_| ())
+    }
+
+    /// Returns the reply to the packet, `None` if the reply is sent once the machine stops
+    fn handle(&mut self, packet: &str) -> Result<Option<String>, ()> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => self.inspect(|m| {
+                (0..REGISTER_SIZES.len())
+                    .map(|n| to_hex(&read_register(m, n)))
+                    .collect()
+            })?,
+            Some(b'G') => match from_hex(&packet[1..]) {
+                Some(data) => self.inspect(move |m| {
+                    let mut data = &data[..];
+                    for (n, size) in REGISTER_SIZES.iter().enumerate() {
+                        if data.len() < *size {
+                            break;
+                        }
+                        write_register(m, n, &data[..*size]);
+                        data = &data[*size..];
+                    }
+                    "OK".to_string()
+                })?,
+                None => "E01".to_string(),
+            },
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(n) if n < REGISTER_SIZES.len() => {
+                    self.inspect(move |m| to_hex(&read_register(m, n)))?
+                },
+                _ => "E01".to_string(),
+            },
+            Some(b'P') => match parse_register_write(&packet[1..]) {
+                Some((n, data)) => self.inspect(move |m| {
+                    write_register(m, n, &data);
+                    "OK".to_string()
+                })?,
+                None => "E01".to_string(),
+            },
+            Some(b'm') => self.read_memory(&packet[1..])?,
+            Some(b'M') => self.write_memory(&packet[1..])?,
+            Some(b'c') => {
+                self.resume(Command::Continue)?;
+                return Ok(None);
+            },
+            Some(b's') => {
+                self.resume(Command::Step)?;
+                return Ok(None);
+            },
+            Some(b'Z') | Some(b'z') => self.handle_breakpoint(packet)?,
+            Some(b'D') => {
+                self.commands.send(Command::Continue).map_err(|_| ())?;
+                "OK".to_string()
+            },
+            Some(b'H') => "OK".to_string(),
+            _ => match packet.split(':').next() {
+                Some("qSupported") => "PacketSize=1000".to_string(),
+                Some("qAttached") => "1".to_string(),
+                Some("qC") => "QC1".to_string(),
+                Some("qfThreadInfo") => "m1".to_string(),
+                Some("qsThreadInfo") => "l".to_string(),
+                _ => String::new(),
+            },
+        };
+
+        Ok(Some(reply))
+    }
+
+    /// Handles `addr,len` of a `m` packet
+    fn read_memory(&self, packet: &str) -> Result<String, ()> {
+        match parse_range(packet) {
+            Some((addr, len)) => {
+                self.inspect(
+                    move |m| match m.memory().get(addr..addr.saturating_add(len)) {
+                        Some(data) => to_hex(data),
+                        None => "E01".to_string(),
+                    },
+                )
+            },
+            None => Ok("E01".to_string()),
+        }
+    }
+
+    /// Handles `addr,len:data` of a `M` packet
+    fn write_memory(&self, packet: &str) -> Result<String, ()> {
+        let mut parts = packet.splitn(2, ':');
+        let range = parts.next().and_then(parse_range);
+        let data = parts.next().and_then(from_hex);
+
+        match (range, data) {
+            (Some((addr, len)), Some(data)) if data.len() == len => self.inspect(move |m| match m
+                .memory_mut()
+                .get_mut(addr..addr.saturating_add(len))
+            {
+                Some(memory) => {
+                    memory.copy_from_slice(&data);
+                    "OK".to_string()
+                },
+                None => "E01".to_string(),
+            }),
+            _ => Ok("E01".to_string()),
+        }
+    }
+
+    fn resume(&mut self, command: Command) -> Result<(), ()> {
+        self.waiting_for_stop = true;
+        self.commands.send(command).map_err(|_| ())
+    }
+
+    /// `Z0` software breakpoints, `Z2`/`Z3`/`Z4` write/read/access watchpoints
+    fn handle_breakpoint(&mut self, packet: &str) -> Result<String, ()> {
+        let insert = packet.starts_with('Z');
+        let mut parts = packet[1..].split(',');
+        let kind = parts.next();
+        let addr = parts.next().and_then(|a| usize::from_str_radix(a, 16).ok());
+        let len = parts
+            .next()
+            .and_then(|l| usize::from_str_radix(l, 16).ok())
+            .unwrap_or(1)
+            .max(1);
+
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return Ok("E01".to_string()),
+        };
+
+        let accesses: &[Access] = match kind {
+            Some("0") => {
+                let command = if insert {
+                    Command::AddBreakpoint(addr)
+                } else {
+                    Command::RemoveBreakpoint(addr)
+                };
+                self.commands.send(command).map_err(|_| ())?;
+                return Ok("OK".to_string());
+            },
+            Some("2") => &[Access::Write],
+            Some("3") => &[Access::Read],
+            Some("4") => &[Access::Read, Access::Write],
+            _ => return Ok(String::new()),
+        };
+
+        let end = match addr.checked_add(len - 1) {
+            Some(end) => end,
+            None => return Ok("E01".to_string()),
+        };
+        let watchpoints: Vec<_> = accesses
+            .iter()
+            .map(|access| Watchpoint {
+                access: *access,
+                start: addr,
+                end,
+            })
+            .collect();
+        self.inspect(move |m| {
+            for watchpoint in watchpoints {
+                if insert {
+                    m.add_watchpoint(watchpoint);
+                } else {
+                    m.remove_watchpoint(watchpoint);
+                }
+            }
+        })?;
+
+        Ok("OK".to_string())
+    }
+}
+
+fn read_register(machine: &Machine, n: usize) -> Vec<u8> {
+    match n {
+        0..=15 => vec![machine.registers()[n]],
+        16 => (machine.i() as u16).to_le_bytes().to_vec(),
+        17 => (machine.pc() as u16).to_le_bytes().to_vec(),
+        18 => vec![machine.stack().len() as u8],
+        19 => vec![machine.delay()],
+        _ => vec![machine.sound()],
+    }
+}
+
+fn write_register(machine: &mut Machine, n: usize, data: &[u8]) {
+    let value = data
+        .iter()
+        .rev()
+        .fold(0usize, |a, b| (a << 8) | *b as usize);
+
+    match n {
+        0..=15 => machine.set_register(n, value as _),
+        16 => machine.set_i(value),
+        17 => machine.set_pc(value),
+        18 => machine.set_stack_len(value),
+        19 => machine.set_delay(value as _),
+        _ => machine.set_sound(value as _),
+    }
+}
+
+/// Parses `n=value` of a `P` packet
+fn parse_register_write(s: &str) -> Option<(usize, Vec<u8>)> {
+    let mut parts = s.splitn(2, '=');
+    let n = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let data = from_hex(parts.next()?)?;
+
+    if n < REGISTER_SIZES.len() && data.len() == REGISTER_SIZES[n] {
+        Some((n, data))
+    } else {
+        None
+    }
+}
+
+/// Parses `addr,len`
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let mut parts = s.splitn(2, ',');
+    let addr = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((addr, len))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Both ends of a localhost connection
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (client, server)
+    }
+
+    fn read_string(stream: &mut TcpStream, len: usize) -> String {
+        let mut data = vec![0; len];
+        stream.read_exact(&mut data).unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    /// Runs the `Inspect` commands on a machine until the sender is dropped
+    fn machine_thread() -> (mpsc::Sender<Command>, std::thread::JoinHandle<Machine>) {
+        let (commands, receiver) = mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let mut machine = Machine::new(Default::default(), &[0x12, 0x00]);
+            for command in receiver.iter() {
+                if let Command::Inspect(f) = command {
+                    f(&mut machine);
+                }
+            }
+            machine
+        });
+        (commands, thread)
+    }
+
+    #[test]
+    fn packets() {
+        let (mut client, server) = connection();
+        let (events, received) = mpsc::channel();
+        std::thread::spawn(move || read_packets(server, events));
+        let timeout = Duration::from_secs(5);
+
+        client.write_all(b"+$m200,2#5d").unwrap();
+        assert_eq!(read_string(&mut client, 1), "+");
+        match received.recv_timeout(timeout).unwrap() {
+            Event::Packet(packet) => assert_eq!(packet, "m200,2"),
+            _ => panic!("expected a packet"),
+        }
+
+        client.write_all(b"$m200,2#00").unwrap();
+        assert_eq!(read_string(&mut client, 1), "-");
+
+        client.write_all(b"\x03").unwrap();
+        assert!(matches!(
+            received.recv_timeout(timeout),
+            Ok(Event::Interrupt)
+        ));
+
+        drop(client);
+        assert!(matches!(
+            received.recv_timeout(timeout),
+            Ok(Event::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(from_hex("00a5FF"), Some(vec![0x00, 0xA5, 0xFF]));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(to_hex(&[0x00, 0xA5, 0xFF]), "00a5ff");
+
+        assert_eq!(parse_range("200,1a"), Some((0x200, 0x1A)));
+        assert_eq!(parse_range("200"), None);
+        assert_eq!(parse_range("200,x"), None);
+
+        assert_eq!(
+            parse_register_write("10=0302"),
+            Some((16, vec![0x03, 0x02]))
+        );
+        assert_eq!(parse_register_write("f=ab"), Some((15, vec![0xAB])));
+        assert_eq!(parse_register_write("10=02"), None);
+        assert_eq!(parse_register_write("15=00"), None);
+        assert_eq!(parse_register_write("0"), None);
+    }
+
+    #[test]
+    fn registers_and_memory_round_trip() {
+        let (_client, server) = connection();
+        let (commands, thread) = machine_thread();
+        let mut session = Session {
+            stream: server,
+            commands: &commands,
+            waiting_for_stop: false,
+        };
+        let mut handle = |packet: &str| session.handle(packet).unwrap().unwrap();
+
+        // V0-VF, I=0x345, PC=0x202, SP=0, DT=5, ST=6
+        let registers = "000102030405060708090a0b0c0d0e0f45030202000506";
+        assert_eq!(handle(&format!("G{}", registers)), "OK");
+        assert_eq!(handle("g"), registers);
+        assert_eq!(handle("P5=aa"), "OK");
+        assert_eq!(handle("p5"), "aa");
+        assert_eq!(handle("p15"), "E01");
+
+        assert_eq!(handle("M300,3:c0ffee"), "OK");
+        assert_eq!(handle("m2ff,5"), "00c0ffee00");
+        assert_eq!(handle("M300,2:c0ffee"), "E01");
+        assert_eq!(handle("mfff,2"), "E01");
+        assert_eq!(handle("M1000,1:00"), "E01");
+
+        drop(session);
+        drop(commands);
+        let machine = thread.join().unwrap();
+        assert_eq!(machine.registers()[5], 0xAA);
+        assert_eq!(machine.registers()[15], 0x0F);
+        assert_eq!((machine.i(), machine.pc()), (0x345, 0x202));
+        assert_eq!((machine.delay(), machine.sound()), (5, 6));
+        assert_eq!(&machine.memory()[0x300..0x303], [0xC0, 0xFF, 0xEE]);
+    }
+
+    #[test]
+    fn exit_replies() {
+        let replies = [
+            (Stop::Paused, "$S05#b8"),
+            (Stop::Halted, "$W00#b7"),
+            (Stop::Crashed, "$X0b#ea"),
+        ];
+        for (stop, reply) in replies.iter() {
+            let (mut client, server) = connection();
+            let (commands, _received) = mpsc::channel();
+            let mut session = Session {
+                stream: server,
+                commands: &commands,
+                waiting_for_stop: false,
+            };
+
+            let (events, received) = mpsc::channel();
+            // Not waiting for one yet
+            events.send(Event::Stop(Stop::Paused)).unwrap();
+            events.send(Event::Packet("c".to_string())).unwrap();
+            events.send(Event::Stop(*stop)).unwrap();
+            if *stop == Stop::Paused {
+                events.send(Event::Disconnected).unwrap();
+            }
+            drop(events);
+
+            let result = session.run(&received);
+            assert_eq!(result.is_ok(), *stop == Stop::Paused);
+            assert_eq!(read_string(&mut client, reply.len()), *reply);
+        }
+    }
+}
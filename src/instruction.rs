@@ -22,8 +22,8 @@ pub enum Instruction {
     Add(usize, usize),
     Sub(usize, usize),
     Subn(usize, usize),
-    Shr(usize),
-    Shl(usize),
+    Shr(usize, usize),
+    Shl(usize, usize),
     LoadDelay(usize),
     LoadPressed(usize),
     SetDelay(usize),
@@ -38,6 +38,23 @@ pub enum Instruction {
 
     Clear,
     Draw(usize, usize, u8),
+
+    ScrollDown(u8),
+    ScrollRight,
+    ScrollLeft,
+    LowRes,
+    HighRes,
+    Exit,
+    SetBigSprite(usize),
+    StoreFlags(usize),
+    RestoreFlags(usize),
+
+    SaveRange(usize, usize),
+    LoadRange(usize, usize),
+    LongLoadI,
+    Plane(u8),
+    LoadAudio,
+    SetPitch(usize),
 }
 
 impl std::fmt::Display for Instruction {
@@ -66,8 +83,8 @@ impl std::fmt::Display for Instruction {
             Add(x, y) =>            write!(f, "ADD  V{:X}, V{:X}", x, y),
             Sub(x, y) =>            write!(f, "SUB  V{:X}, V{:X}", x, y),
             Subn(x, y) =>           write!(f, "SUBN V{:X}, V{:X}", x, y),
-            Shr(x) =>               write!(f, "SHR  V{:X}", x),
-            Shl(x) =>               write!(f, "SHL  V{:X}", x),
+            Shr(x, y) =>            write!(f, "SHR  V{:X}, V{:X}", x, y),
+            Shl(x, y) =>            write!(f, "SHL  V{:X}, V{:X}", x, y),
             LoadDelay(x) =>         write!(f, "LD   V{:X}, DT", x),
             LoadPressed(x) =>       write!(f, "LD   V{:X}, K", x),
             SetDelay(x) =>          write!(f, "LD   DT, V{:X}", x),
@@ -82,6 +99,23 @@ impl std::fmt::Display for Instruction {
 
             Clear =>                write!(f, "CLS"),
             Draw(x, y, n) =>        write!(f, "DRW  V{:X}, V{:X}, {:X}", x, y, n),
+
+            ScrollDown(n) =>        write!(f, "SCD  {:X}", n),
+            ScrollRight =>          write!(f, "SCR"),
+            ScrollLeft =>           write!(f, "SCL"),
+            LowRes =>               write!(f, "LOW"),
+            HighRes =>              write!(f, "HIGH"),
+            Exit =>                 write!(f, "EXIT"),
+            SetBigSprite(x) =>      write!(f, "LD   HF, V{:X}", x),
+            StoreFlags(x) =>        write!(f, "LD   R, V{:X}", x),
+            RestoreFlags(x) =>      write!(f, "LD   V{:X}, R", x),
+
+            SaveRange(x, y) =>      write!(f, "SAVE V{:X}, V{:X}", x, y),
+            LoadRange(x, y) =>      write!(f, "LOAD V{:X}, V{:X}", x, y),
+            LongLoadI =>            write!(f, "LD   I, LONG"),
+            Plane(n) =>             write!(f, "PLANE {:X}", n),
+            LoadAudio =>            write!(f, "AUDIO"),
+            SetPitch(x) =>          write!(f, "PITCH V{:X}", x),
         }
     }
 }
@@ -101,16 +135,28 @@ impl Instruction {
         }
 
         match op1 & 0xF0 {
+            0x00 if op1 != 0x00 => None,
             0x00 => match op2 {
+                0xC0..=0xCF => Some(ScrollDown(op2 & 0x0F)),
                 0xE0 => Some(Clear),
                 0xEE => Some(Return),
+                0xFB => Some(ScrollRight),
+                0xFC => Some(ScrollLeft),
+                0xFD => Some(Exit),
+                0xFE => Some(LowRes),
+                0xFF => Some(HighRes),
                 _ => None,
             },
             0x10 => Some(Jump(addr(op1, op2))),
             0x20 => Some(Call(addr(op1, op2))),
             0x30 => Some(SkipEqByte(x(op1), op2)),
             0x40 => Some(SkipNeByte(x(op1), op2)),
-            0x50 if op2 & 0x0F == 0x0 => Some(SkipEq(x(op1), y(op2))),
+            0x50 => match op2 & 0x0F {
+                0x0 => Some(SkipEq(x(op1), y(op2))),
+                0x2 => Some(SaveRange(x(op1), y(op2))),
+                0x3 => Some(LoadRange(x(op1), y(op2))),
+                _ => None,
+            },
             0x60 => Some(LoadByte(x(op1), op2)),
             0x70 => Some(AddByte(x(op1), op2)),
             0x80 => match op2 & 0x0F {
@@ -120,12 +166,12 @@ impl Instruction {
                 0x3 => Some(Xor(x(op1), y(op2))),
                 0x4 => Some(Add(x(op1), y(op2))),
                 0x5 => Some(Sub(x(op1), y(op2))),
-                0x6 => Some(Shr(x(op1))),
+                0x6 => Some(Shr(x(op1), y(op2))),
                 0x7 => Some(Subn(x(op1), y(op2))),
-                0xE => Some(Shl(x(op1))),
+                0xE => Some(Shl(x(op1), y(op2))),
                 _ => None,
             },
-            0x90 => Some(SkipNe(x(op1), y(op2))),
+            0x90 if op2 & 0x0F == 0 => Some(SkipNe(x(op1), y(op2))),
             0xA0 => Some(LoadI(addr(op1, op2))),
             0xB0 => Some(JumpPlus(addr(op1, op2))),
             0xC0 => Some(LoadRandom(x(op1), op2)),
@@ -136,18 +182,161 @@ impl Instruction {
                 _ => None,
             },
             0xF0 => match op2 {
+                0x00 if op1 == 0xF0 => Some(LongLoadI),
+                0x01 => Some(Plane(op1 & 0x0F)),
+                0x02 if op1 == 0xF0 => Some(LoadAudio),
                 0x07 => Some(LoadDelay(x(op1))),
                 0x0A => Some(LoadPressed(x(op1))),
                 0x15 => Some(SetDelay(x(op1))),
                 0x18 => Some(SetSound(x(op1))),
                 0x1E => Some(AddToI(x(op1))),
                 0x29 => Some(SetSprite(x(op1))),
+                0x30 => Some(SetBigSprite(x(op1))),
                 0x33 => Some(StoreBCD(x(op1))),
+                0x3A => Some(SetPitch(x(op1))),
                 0x55 => Some(StoreRegisters(x(op1))),
                 0x65 => Some(RestoreRegisters(x(op1))),
+                0x75 => Some(StoreFlags(x(op1))),
+                0x85 => Some(RestoreFlags(x(op1))),
                 _ => None,
             },
             _ => None,
         }
     }
+
+    /// The inverse of `parse`, `None` if an operand is out of range
+    pub fn try_encode(&self) -> Option<[u8; 2]> {
+        use Instruction::*;
+
+        fn addr(nibble: u8, a: usize) -> Option<[u8; 2]> {
+            if a > 0xFFF {
+                return None;
+            }
+            Some([nibble << 4 | (a >> 8) as u8, a as u8])
+        }
+        fn byte(nibble: u8, x: usize, b: u8) -> Option<[u8; 2]> {
+            if x > 0xF {
+                return None;
+            }
+            Some([nibble << 4 | x as u8, b])
+        }
+        fn op(nibble: u8, x: usize, y: usize, n: u8) -> Option<[u8; 2]> {
+            if y > 0xF || n > 0xF {
+                return None;
+            }
+            byte(nibble, x, (y as u8) << 4 | n)
+        }
+
+        match *self {
+            Jump(a) =>              addr(0x1, a),
+            JumpPlus(a) =>          addr(0xB, a),
+            Call(a) =>              addr(0x2, a),
+            Return =>               Some([0x00, 0xEE]),
+
+            SkipEqByte(x, b) =>     byte(0x3, x, b),
+            SkipNeByte(x, b) =>     byte(0x4, x, b),
+            SkipEq(x, y) =>         op(0x5, x, y, 0x0),
+            SkipNe(x, y) =>         op(0x9, x, y, 0x0),
+            SkipPressed(x) =>       byte(0xE, x, 0x9E),
+            SkipNPressed(x) =>      byte(0xE, x, 0xA1),
+
+            LoadByte(x, b) =>       byte(0x6, x, b),
+            LoadRandom(x, b) =>     byte(0xC, x, b),
+            AddByte(x, b) =>        byte(0x7, x, b),
+            Copy(x, y) =>           op(0x8, x, y, 0x0),
+            Or(x, y) =>             op(0x8, x, y, 0x1),
+            And(x, y) =>            op(0x8, x, y, 0x2),
+            Xor(x, y) =>            op(0x8, x, y, 0x3),
+            Add(x, y) =>            op(0x8, x, y, 0x4),
+            Sub(x, y) =>            op(0x8, x, y, 0x5),
+            Subn(x, y) =>           op(0x8, x, y, 0x7),
+            Shr(x, y) =>            op(0x8, x, y, 0x6),
+            Shl(x, y) =>            op(0x8, x, y, 0xE),
+            LoadDelay(x) =>         byte(0xF, x, 0x07),
+            LoadPressed(x) =>       byte(0xF, x, 0x0A),
+            SetDelay(x) =>          byte(0xF, x, 0x15),
+            SetSound(x) =>          byte(0xF, x, 0x18),
+
+            LoadI(a) =>             addr(0xA, a),
+            AddToI(x) =>            byte(0xF, x, 0x1E),
+            SetSprite(x) =>         byte(0xF, x, 0x29),
+            StoreBCD(x) =>          byte(0xF, x, 0x33),
+            StoreRegisters(x) =>    byte(0xF, x, 0x55),
+            RestoreRegisters(x) =>  byte(0xF, x, 0x65),
+
+            Clear =>                Some([0x00, 0xE0]),
+            Draw(x, y, n) =>        op(0xD, x, y, n),
+
+            ScrollDown(n) =>        op(0x0, 0x0, 0xC, n),
+            ScrollRight =>          Some([0x00, 0xFB]),
+            ScrollLeft =>           Some([0x00, 0xFC]),
+            LowRes =>               Some([0x00, 0xFE]),
+            HighRes =>              Some([0x00, 0xFF]),
+            Exit =>                 Some([0x00, 0xFD]),
+            SetBigSprite(x) =>      byte(0xF, x, 0x30),
+            StoreFlags(x) =>        byte(0xF, x, 0x75),
+            RestoreFlags(x) =>      byte(0xF, x, 0x85),
+
+            SaveRange(x, y) =>      op(0x5, x, y, 0x2),
+            LoadRange(x, y) =>      op(0x5, x, y, 0x3),
+            LongLoadI =>            Some([0xF0, 0x00]),
+            Plane(n) =>             byte(0xF, n as usize, 0x01),
+            LoadAudio =>            Some([0xF0, 0x02]),
+            SetPitch(x) =>          byte(0xF, x, 0x3A),
+        }
+    }
+
+    /// The inverse of `parse`
+    ///
+    /// # Panics
+    ///
+    /// If an operand is out of range, like a register above VF or an address above 0xFFF, see
+    /// `try_encode`
+    pub fn encode(&self) -> [u8; 2] {
+        match self.try_encode() {
+            Some(op) => op,
+            None => panic!("operand out of range in {:?}", self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction::{self, *};
+
+    #[test]
+    fn encode_round_trips_every_opcode() {
+        for opcode in 0..=0xFFFFu16 {
+            let [a, b] = opcode.to_be_bytes();
+            if let Some(instr) = Instruction::parse(a, b) {
+                assert_eq!(
+                    instr.try_encode(),
+                    Some([a, b]),
+                    "{:04X} {:?}",
+                    opcode,
+                    instr
+                );
+                assert_eq!(instr.encode(), [a, b], "{:04X} {:?}", opcode, instr);
+            }
+        }
+    }
+
+    #[test]
+    fn try_encode_rejects_out_of_range_operands() {
+        assert_eq!(Draw(0, 0, 16).try_encode(), None);
+        assert_eq!(Jump(0x1000).try_encode(), None);
+        assert_eq!(Call(0x1000).try_encode(), None);
+        assert_eq!(LoadI(0x1000).try_encode(), None);
+        assert_eq!(LoadByte(16, 0).try_encode(), None);
+        assert_eq!(Add(16, 0).try_encode(), None);
+        assert_eq!(Add(0, 16).try_encode(), None);
+        assert_eq!(Plane(16).try_encode(), None);
+        assert_eq!(ScrollDown(16).try_encode(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn encode_panics_out_of_range() {
+        Draw(0, 0, 16).encode();
+    }
 }
@@ -0,0 +1,258 @@
+//! Mapping of the hex keypad to the keyboard
+//!
+//! Keymap files are TOML, every keypad key is bound to one or more keys by name or by scancode,
+//! and tables under `[rom."<file name>"]` override some of them for one ROM:
+//!
+//! ```toml
+//! [keys]
+//! 5 = ["w", "up"]
+//! 8 = ["s", "down"]
+//! A = 44
+//!
+//! [rom."pong.ch8"]
+//! 1 = "a"
+//! 4 = "z"
+//! ```
+//!
+//! Names are glutin's `VirtualKeyCode` names in lowercase with digits as is: `a`, `1`, `space`,
+//! `return`, `up`, `numpad5`... Scancodes are only seen by the window frontend. Hotkeys can't be
+//! bound, they would press keypad keys along with what they do.
+
+use std::convert::TryFrom;
+use std::path::Path;
+
+/// Keys used by the frontends for hotkeys, in the window and in the terminal
+pub const HOTKEYS: &[&str] = &[
+    "back", "f5", "f6", "f7", "f9", "f12", "m", "p", "n", "i", "t", "minus", "equals", "lbracket",
+    "rbracket", "-", "=", "[", "]",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Binding {
+    Name(String),
+    Scancode(u32),
+}
+
+/// Keyboard keys of every keypad key
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    pub keys: [Vec<Binding>; 16],
+}
+
+impl Default for Keymap {
+    /// 1234, QWER, ASDF, ZXCV
+    fn default() -> Self {
+        let mut keys: [Vec<Binding>; 16] = Default::default();
+        for (key, name) in keys.iter_mut().zip("x123qweasdzc4rfv".chars()) {
+            key.push(Binding::Name(name.to_string()));
+        }
+        Self { keys }
+    }
+}
+
+impl Keymap {
+    /// Parses a keymap file, applying the overrides of the ROM named `rom`
+    pub fn parse(source: &str, rom: &str) -> Result<Self, String> {
+        let table: toml::Table = source.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let mut keymap = Self::default();
+
+        for (name, value) in table.iter() {
+            match (name.as_str(), value) {
+                ("keys", toml::Value::Table(keys)) => keymap.bind(keys)?,
+                ("rom", toml::Value::Table(roms)) => {
+                    for (name, keys) in roms.iter() {
+                        match keys {
+                            toml::Value::Table(keys) if name == rom => keymap.bind(keys)?,
+                            // Checked anyway, mistakes show up with any ROM
+                            toml::Value::Table(keys) => Self::default().bind(keys)?,
+                            _ => return Err(format!("rom.\"{}\" is not a table", name)),
+                        }
+                    }
+                },
+                _ => return Err(format!("unknown section '{}'", name)),
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    /// Reads `path`, applying the overrides of the ROM `rom`
+    pub fn load(path: impl AsRef<Path>, rom: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let rom = rom
+            .as_ref()
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("");
+
+        Self::parse(&source, rom).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// `$XDG_CONFIG_HOME/chip8/keymap.toml` or `~/.config/chip8/keymap.toml`
+    pub fn default_path() -> Option<std::path::PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(Into::into)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config.join("chip8").join("keymap.toml"))
+    }
+
+    /// Replaces the bindings of the keypad keys in `keys`
+    fn bind(&mut self, keys: &toml::Table) -> Result<(), String> {
+        // `a` and `A` are different TOML keys
+        let mut bound = [false; 16];
+
+        for (key, value) in keys.iter() {
+            let i = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|i| key.len() == 1 && *i < 16)
+                .ok_or_else(|| format!("'{}' is not a keypad key", key))?;
+            if std::mem::replace(&mut bound[i as usize], true) {
+                return Err(format!("keypad key {:X} is bound twice", i));
+            }
+
+            let values = match value {
+                toml::Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            self.keys[i as usize] = values
+                .into_iter()
+                .map(|value| match value {
+                    toml::Value::String(name) if HOTKEYS.contains(&&*name.to_lowercase()) => {
+                        Err(format!("key {}: '{}' is a hotkey", key, name))
+                    },
+                    toml::Value::String(name) => Ok(Binding::Name(name.to_lowercase())),
+                    toml::Value::Integer(code) if u32::try_from(*code).is_ok() => {
+                        Ok(Binding::Scancode(*code as u32))
+                    },
+                    _ => Err(format!(
+                        "key {}: {} is neither a name nor a scancode",
+                        key, value
+                    )),
+                })
+                .collect::<Result<_, _>>()?;
+        }
+
+        Ok(())
+    }
+
+    /// Keypad keys bound to the key `name` or `scancode`
+    pub fn find<'a>(
+        &'a self,
+        name: Option<&'a str>,
+        scancode: Option<u32>,
+    ) -> impl Iterator<Item = usize> + 'a {
+        self.keys
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, bindings)| {
+                bindings
+                    .iter()
+                    .any(|binding| match binding {
+                        Binding::Name(n) => Some(n.as_str()) == name,
+                        Binding::Scancode(s) => Some(*s) == scancode,
+                    })
+                    .then_some(i)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Binding {
+        Binding::Name(name.to_string())
+    }
+
+    fn error(source: &str) -> String {
+        Keymap::parse(source, "").unwrap_err()
+    }
+
+    const SOURCE: &str = r#"
+        [keys]
+        5 = ["W", "up"]
+        8 = "s"
+        A = 44
+
+        [rom."pong.ch8"]
+        1 = "a"
+        4 = ["z", 30]
+
+        [rom."other.ch8"]
+        1 = "b"
+    "#;
+
+    #[test]
+    fn bindings() {
+        let keymap = Keymap::parse(SOURCE, "game.ch8").unwrap();
+        assert_eq!(keymap.keys[5], [name("w"), name("up")]);
+        assert_eq!(keymap.keys[8], [name("s")]);
+        assert_eq!(keymap.keys[0xA], [Binding::Scancode(44)]);
+        // Left as they were
+        assert_eq!(keymap.keys[1], [name("1")]);
+        assert_eq!(keymap.keys[0], [name("x")]);
+    }
+
+    #[test]
+    fn rom_overrides() {
+        let keymap = Keymap::parse(SOURCE, "pong.ch8").unwrap();
+        assert_eq!(keymap.keys[1], [name("a")]);
+        assert_eq!(keymap.keys[4], [name("z"), Binding::Scancode(30)]);
+        assert_eq!(keymap.keys[5], [name("w"), name("up")]);
+    }
+
+    #[test]
+    fn find() {
+        let keymap = Keymap::parse("[keys]\n1 = \"q\"\n2 = [\"q\", 16]", "").unwrap();
+        assert_eq!(keymap.find(Some("q"), None).collect::<Vec<_>>(), [1, 2, 4]);
+        assert_eq!(keymap.find(None, Some(16)).collect::<Vec<_>>(), [2]);
+        assert_eq!(keymap.find(Some("up"), None).count(), 0);
+    }
+
+    #[test]
+    fn invalid_toml() {
+        assert!(Keymap::parse("[keys\n1 = \"a\"", "").is_err());
+        assert!(Keymap::parse("[keys]\n1 = \"a\"\n1 = \"b\"", "").is_err());
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(error("[keys]\nG = \"a\""), "'G' is not a keypad key");
+        assert_eq!(error("[keys]\n10 = \"a\""), "'10' is not a keypad key");
+        assert_eq!(error("[keys]\n01 = \"a\""), "'01' is not a keypad key");
+        assert_eq!(error("[rom.\"x\"]\nG = \"a\""), "'G' is not a keypad key");
+        assert_eq!(error("[kyes]\n1 = \"a\""), "unknown section 'kyes'");
+        assert_eq!(error("rom = 1"), "unknown section 'rom'");
+        assert_eq!(error("[rom]\nx = 1"), "rom.\"x\" is not a table");
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert_eq!(
+            error("[keys]\n1 = -1"),
+            "key 1: -1 is neither a name nor a scancode"
+        );
+        assert_eq!(
+            error("[keys]\n1 = 4294967296"),
+            "key 1: 4294967296 is neither a name nor a scancode"
+        );
+        assert_eq!(
+            error("[keys]\n1 = [\"a\", true]"),
+            "key 1: true is neither a name nor a scancode"
+        );
+        assert_eq!(error("[keys]\n1 = \"F5\""), "key 1: 'F5' is a hotkey");
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        assert_eq!(
+            error("[keys]\na = \"j\"\nA = \"k\""),
+            "keypad key A is bound twice"
+        );
+        // Overriding the keys table is fine
+        let keymap = Keymap::parse("[keys]\na = \"j\"\n[rom.\"x\"]\nA = \"k\"", "x").unwrap();
+        assert_eq!(keymap.keys[0xA], [name("k")]);
+    }
+}
@@ -1,5 +1,101 @@
 use crate::instruction::Instruction;
-use crate::screen::{Screen, SPRITES};
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::random::Random;
+use crate::screen::{Screen, BIG_SPRITES, HEIGHT, SPRITES};
+use crate::sound::Tone;
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::trace::{Entry, Tracer};
+
+const BIG_SPRITES_POS: usize = SPRITES.len();
+
+/// Machine cycles of a 60hz frame on the COSMAC VIP, 1.76 MHz with 8 clocks per cycle
+const VIP_FRAME_CYCLES: u32 = 3668;
+/// Taken every frame by the display DMA (128 lines of 8 bytes) and the interrupt routine
+const VIP_INTERRUPT_CYCLES: u32 = 1024 + 30;
+/// Left to the interpreter every frame
+const VIP_FRAME_BUDGET: u32 = VIP_FRAME_CYCLES - VIP_INTERRUPT_CYCLES;
+/// Fetching and decoding an instruction
+const VIP_FETCH_CYCLES: u32 = 40;
+
+#[derive(Clone, Copy, Debug)]
+pub enum MachineError {
+    InvalidOpcode {
+        address: usize,
+        opcode: [u8; 2],
+    },
+    StackOverflow {
+        address: usize,
+    },
+    StackUnderflow {
+        address: usize,
+    },
+    PcOutOfRange {
+        address: usize,
+    },
+    IOutOfBounds {
+        address: usize,
+        i: usize,
+        len: usize,
+    },
+}
+
+impl std::fmt::Display for MachineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use MachineError::*;
+        match self {
+            InvalidOpcode { address, opcode } => write!(
+                f,
+                "invalid opcode {:02X}{:02X} at 0x{:03X}",
+                opcode[0], opcode[1], address
+            ),
+            StackOverflow { address } => write!(f, "stack overflow at 0x{:03X}", address),
+            StackUnderflow { address } => write!(f, "stack underflow at 0x{:03X}", address),
+            PcOutOfRange { address } => {
+                write!(f, "program counter out of range: 0x{:03X}", address)
+            },
+            IOutOfBounds { address, i, len } => write!(
+                f,
+                "I out of bounds at 0x{:03X}: I = 0x{:03X}, accessing {} bytes",
+                address, i, len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MachineError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl std::fmt::Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+/// Watches `access` to memory[start..=end]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub access: Access,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WatchHit {
+    pub watchpoint: Watchpoint,
+    pub address: usize,
+    pub pc: usize,
+    pub instruction: Option<Instruction>,
+}
 
 enum LoadKeyState {
     None,
@@ -8,10 +104,9 @@ enum LoadKeyState {
 }
 
 pub struct Machine {
-    freq_multiplier: u8,
-    timer_decrease: u8,
+    quirks: Quirks,
 
-    memory: [u8; 4096],
+    memory: Vec<u8>,
     memory_pos: usize,
 
     stack: [usize; 16],
@@ -23,20 +118,40 @@ pub struct Machine {
     sound: u8,
 
     load_key: LoadKeyState,
+    rpl: [u8; 16],
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    halted: bool,
+    random: Random,
+
+    /// VIP machine cycles used in the current frame, more than the budget if the last
+    /// instruction ran over into the next one
+    cycles: u32,
+    /// No instruction ran since the timers ticked, `Draw` waits for that in VIP timing
+    vblank: bool,
+    /// The keypad was read since the last `take_input_polled`
+    input_polled: bool,
+
+    watchpoints: Vec<Watchpoint>,
+    watch_hits: Vec<WatchHit>,
+    tracer: Option<Box<dyn Tracer>>,
 
     screen: Screen,
 }
 
 impl Machine {
-    pub fn new(freq_multiplier: u8, program: &[u8]) -> Self {
-        let mut memory = [0; 4096];
+    /// # Panics
+    ///
+    /// If the program does not fit in memory after 0x200, `open` checks that
+    pub fn new(quirks: Quirks, program: &[u8]) -> Self {
+        let mut memory = vec![0; quirks.memory_size];
         memory[0..SPRITES.len()].copy_from_slice(&SPRITES);
+        memory[BIG_SPRITES_POS..BIG_SPRITES_POS + BIG_SPRITES.len()].copy_from_slice(&BIG_SPRITES);
 
         memory[0x200..0x200 + program.len()].copy_from_slice(program);
 
         Self {
-            freq_multiplier,
-            timer_decrease: 0,
+            quirks,
 
             memory,
             memory_pos: 0x200,
@@ -50,51 +165,226 @@ impl Machine {
             sound: 0,
 
             load_key: LoadKeyState::None,
+            rpl: [0; 16],
+            audio_pattern: [0; 16],
+            pitch: 64,
+            halted: false,
+            random: Random::new(0, false),
+
+            cycles: 0,
+            vblank: true,
+            input_polled: false,
+
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            tracer: None,
 
             screen: Screen::new(),
         }
     }
 
-    pub fn open(freq_multiplier: u8, path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+    pub fn open(quirks: Quirks, path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
         use std::io::Read;
 
         let mut file = std::fs::File::open(path)?;
         let mut program = Vec::new();
         file.read_to_end(&mut program)?;
 
-        Ok(Self::new(freq_multiplier, &program))
+        let room = quirks.memory_size.saturating_sub(0x200);
+        if program.len() > room {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "ROM is {} bytes, only {} fit in memory",
+                    program.len(),
+                    room
+                ),
+            ));
+        }
+
+        Ok(Self::new(quirks, &program))
     }
 
-    pub fn step(&mut self, pressed_keys: [bool; 16]) {
+    /// Runs `ipf` instructions, then ticks the timers once
+    pub fn run_frame(&mut self, ipf: u32, pressed_keys: [bool; 16]) -> Result<(), MachineError> {
+        for _ in 0..ipf {
+            self.step(pressed_keys)?;
+        }
+        self.tick_timers();
+
+        Ok(())
+    }
+
+    /// Runs instructions for the VIP machine cycles of one frame, then ticks the timers once
+    pub fn run_vip_frame(&mut self, pressed_keys: [bool; 16]) -> Result<(), MachineError> {
+        while !self.halted && !self.vip_frame_done() {
+            self.vip_step(pressed_keys)?;
+        }
+        self.tick_timers();
+
+        Ok(())
+    }
+
+    /// Whether the VIP machine cycles of the frame are used up or the next `Draw` has to wait for
+    /// the display interrupt
+    pub fn vip_frame_done(&self) -> bool {
+        self.cycles >= VIP_FRAME_BUDGET
+            || !self.vblank && matches!(self.current_instruction(), Some(Instruction::Draw(..)))
+    }
+
+    /// Runs one instruction, charging its VIP machine cycles to the frame
+    pub fn vip_step(&mut self, pressed_keys: [bool; 16]) -> Result<(), MachineError> {
+        let waiting = !matches!(self.load_key, LoadKeyState::None);
+        let instr = self.current_instruction();
+        let cycles = instr.map_or(0, |instr| self.vip_cycles(instr));
+        let pc = self.memory_pos;
+
+        self.step(pressed_keys)?;
+
+        self.vblank = false;
+        if waiting {
+            // The keypad is scanned once per frame
+            self.cycles = self.cycles.max(VIP_FRAME_BUDGET);
+        } else {
+            let skip = matches!(
+                instr,
+                Some(
+                    Instruction::SkipEqByte(..)
+                        | Instruction::SkipNeByte(..)
+                        | Instruction::SkipEq(..)
+                        | Instruction::SkipNe(..)
+                        | Instruction::SkipPressed(..)
+                        | Instruction::SkipNPressed(..)
+                )
+            );
+            let skipped = skip && self.memory_pos == pc + 4;
+            self.cycles += cycles + if skipped { 4 } else { 0 };
+        }
+
+        Ok(())
+    }
+
+    /// Estimated cost of `instr` in the COSMAC VIP interpreter, skipping excluded. These aren't read
+    /// off a cycle table, so they are off by a few cycles: every instruction is charged the same
+    /// `VIP_FETCH_CYCLES` for fetching and dispatching, only the data dependent costs below vary
+    /// (pages crossed by `JumpPlus`/`AddToI`, the digits of `StoreBCD`, the register count of
+    /// `StoreRegisters`/`RestoreRegisters`, the height and byte alignment of `Draw`), and the
+    /// instructions the VIP doesn't have cost as much as a jump
+    fn vip_cycles(&self, instr: Instruction) -> u32 {
+        use Instruction::*;
+        let page = |addr: usize| addr >> 8;
+
+        VIP_FETCH_CYCLES
+            + match instr {
+                Clear => 3078,
+                Return => 10,
+                Jump(_) => 12,
+                JumpPlus(a) => {
+                    let target = a + self.registers[0] as usize;
+                    22 + if page(target) != page(a) { 2 } else { 0 }
+                },
+                Call(_) => 26,
+
+                SkipEqByte(..) | SkipNeByte(..) => 10,
+                SkipEq(..) | SkipNe(..) => 14,
+                SkipPressed(_) | SkipNPressed(_) => 14,
+
+                LoadByte(..) => 6,
+                LoadRandom(..) => 36,
+                AddByte(..) => 10,
+                Copy(..) => 12,
+                Or(..) | And(..) | Xor(..) | Add(..) | Sub(..) | Subn(..) | Shr(..) | Shl(..) => 44,
+                LoadDelay(_) | SetDelay(_) | SetSound(_) => 10,
+                LoadPressed(_) => 18,
+
+                LoadI(_) => 12,
+                AddToI(x) => {
+                    let target = self.i + self.registers[x] as usize;
+                    16 + if page(target) != page(self.i) { 2 } else { 0 }
+                },
+                SetSprite(_) => 16,
+                StoreBCD(x) => {
+                    let v = self.registers[x] as u32;
+                    80 + 16 * (v / 100 + v / 10 % 10 + v % 10)
+                },
+                StoreRegisters(x) | RestoreRegisters(x) => 14 + 14 * (x as u32 + 1),
+
+                // Sprites not aligned to a byte are shifted and drawn over two bytes
+                Draw(x, _, n) => {
+                    let row = if self.registers[x] & 7 == 0 { 46 } else { 68 };
+                    26 + row * u32::from(n.max(1))
+                },
+
+                // Not on the VIP
+                _ => 12,
+            }
+    }
+
+    /// Decrements the delay and sound timers, once per 60hz frame
+    pub fn tick_timers(&mut self) {
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+
+        self.cycles = self.cycles.saturating_sub(VIP_FRAME_BUDGET);
+        self.vblank = true;
+    }
+
+    pub fn step(&mut self, pressed_keys: [bool; 16]) -> Result<(), MachineError> {
+        let result = self.execute_next(pressed_keys);
+        if let (Err(e), Some(tracer)) = (&result, &mut self.tracer) {
+            tracer.crashed(e);
+        }
+        result
+    }
+
+    fn execute_next(&mut self, pressed_keys: [bool; 16]) -> Result<(), MachineError> {
+        if self.halted {
+            return Ok(());
+        }
+
         match self.load_key {
             LoadKeyState::None => {
-                if self.timer_decrease == 0 {
-                    if self.delay != 0 {
-                        self.delay -= 1;
-                    }
-                    if self.sound != 0 {
-                        self.sound -= 1;
-                    }
-                    self.timer_decrease = self.freq_multiplier;
+                if self.memory_pos + 1 >= self.memory.len() {
+                    return Err(MachineError::PcOutOfRange {
+                        address: self.memory_pos,
+                    });
                 }
-                self.timer_decrease -= 1;
 
-                let instr = Instruction::parse(
-                    self.memory[self.memory_pos],
-                    self.memory[self.memory_pos + 1],
-                )
-                .unwrap();
-
-                println!(
-                    "0x{:03X}: ({:02X}{:02X}) {}",
-                    self.memory_pos,
-                    self.memory[self.memory_pos],
-                    self.memory[self.memory_pos + 1],
-                    instr
+                let mut opcode = [0; 2];
+                self.read_memory(Access::Execute, self.memory_pos, &mut opcode);
+                let instr = Instruction::parse(opcode[0], opcode[1]).ok_or(
+                    MachineError::InvalidOpcode {
+                        address: self.memory_pos,
+                        opcode,
+                    },
+                )?;
+
+                self.random.step();
+                self.input_polled |= matches!(
+                    instr,
+                    Instruction::SkipPressed(_)
+                        | Instruction::SkipNPressed(_)
+                        | Instruction::LoadPressed(_)
                 );
-                self.execute_instruction(instr, pressed_keys);
+                let (pc, before) = (self.memory_pos, self.registers);
+                let result = self.execute_instruction(instr, pressed_keys);
+
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.trace(&Entry {
+                        pc,
+                        opcode,
+                        instruction: instr,
+                        before,
+                        after: self.registers,
+                        i: self.i,
+                        delay: self.delay,
+                        sound: self.sound,
+                    });
+                }
+                result?;
             },
             LoadKeyState::WaitPress { reg } => {
+                self.input_polled = true;
                 for (i, key) in pressed_keys.iter().enumerate() {
                     if *key {
                         self.load_key = LoadKeyState::WaitRelease { reg, key: i };
@@ -103,15 +393,87 @@ impl Machine {
                 }
             },
             LoadKeyState::WaitRelease { reg, key } => {
+                self.input_polled = true;
                 if !pressed_keys[key] {
                     self.load_key = LoadKeyState::None;
                     self.registers[reg] = key as _;
                 }
             },
         }
+
+        Ok(())
+    }
+
+    fn check_i(&self, len: usize) -> Result<(), MachineError> {
+        if self.i + len > self.memory.len() {
+            return Err(MachineError::IOutOfBounds {
+                address: self.memory_pos,
+                i: self.i,
+                len,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Reports accesses to the watched memory ranges
+    fn watch(&mut self, access: Access, start: usize, len: usize) {
+        // Drawing with no plane selected reads nothing
+        if len == 0 {
+            return;
+        }
+
+        let end = start + len - 1;
+        for watchpoint in self.watchpoints.iter() {
+            if watchpoint.access == access && start <= watchpoint.end && end >= watchpoint.start {
+                self.watch_hits.push(WatchHit {
+                    watchpoint: *watchpoint,
+                    address: start.max(watchpoint.start),
+                    pc: self.memory_pos,
+                    instruction: self.current_instruction(),
+                });
+            }
+        }
+    }
+
+    // All memory accesses made by the program go through read_memory and write_memory,
+    // bounds must be checked beforehand
+
+    fn read_memory(&mut self, access: Access, start: usize, buf: &mut [u8]) {
+        if !self.watchpoints.is_empty() {
+            self.watch(access, start, buf.len());
+        }
+        buf.copy_from_slice(&self.memory[start..start + buf.len()]);
     }
 
-    fn execute_instruction(&mut self, instr: Instruction, pressed_keys: [bool; 16]) {
+    fn write_memory(&mut self, start: usize, data: &[u8]) {
+        if !self.watchpoints.is_empty() {
+            self.watch(Access::Write, start, data.len());
+        }
+        self.memory[start..start + data.len()].copy_from_slice(data);
+    }
+
+    /// Length of the instruction after the current one, `F000 NNNN` takes 4 bytes
+    fn skip_len(&self) -> usize {
+        match self.memory.get(self.memory_pos + 2..self.memory_pos + 4) {
+            Some([0xF0, 0x00]) => 4,
+            _ => 2,
+        }
+    }
+
+    fn increment_i(&mut self, x: usize) {
+        match self.quirks.index_increment {
+            IndexIncrement::Unchanged => {},
+            IndexIncrement::X => self.i += x,
+            IndexIncrement::XPlusOne => self.i += x + 1,
+        }
+    }
+
+    fn execute_instruction(
+        &mut self,
+        instr: Instruction,
+        pressed_keys: [bool; 16],
+    ) -> Result<(), MachineError> {
         let mut increase_mem_pos = 2;
 
         match instr {
@@ -121,12 +483,18 @@ impl Machine {
                 increase_mem_pos = 0;
             },
             Instruction::JumpPlus(a) => {
-                // Jump to V0 + a
-                self.memory_pos = self.registers[0] as usize + a;
+                // Jump to V0 + a (or Vx + a, x being the highest nibble of a)
+                let reg = if self.quirks.jump_uses_vx { a >> 8 } else { 0 };
+                self.memory_pos = self.registers[reg] as usize + a;
                 increase_mem_pos = 0;
             },
             Instruction::Call(a) => {
                 // Call procedure at a
+                if self.stack_pos == self.stack.len() {
+                    return Err(MachineError::StackOverflow {
+                        address: self.memory_pos,
+                    });
+                }
                 self.stack[self.stack_pos] = self.memory_pos + 2;
                 self.stack_pos += 1;
                 self.memory_pos = a;
@@ -134,6 +502,11 @@ impl Machine {
             },
             Instruction::Return => {
                 // Return from procedure
+                if self.stack_pos == 0 {
+                    return Err(MachineError::StackUnderflow {
+                        address: self.memory_pos,
+                    });
+                }
                 self.stack_pos -= 1;
                 self.memory_pos = self.stack[self.stack_pos];
                 increase_mem_pos = 0;
@@ -142,37 +515,37 @@ impl Machine {
             Instruction::SkipEqByte(x, b) => {
                 // Skip instruction if Vx == b
                 if self.registers[x] == b {
-                    increase_mem_pos += 2;
+                    increase_mem_pos += self.skip_len();
                 }
             },
             Instruction::SkipNeByte(x, b) => {
                 // Skip instruction if Vx != b
                 if self.registers[x] != b {
-                    increase_mem_pos += 2;
+                    increase_mem_pos += self.skip_len();
                 }
             },
             Instruction::SkipEq(x, y) => {
                 // Skip instruction if Vx == Vy
                 if self.registers[x] == self.registers[y] {
-                    increase_mem_pos += 2;
+                    increase_mem_pos += self.skip_len();
                 }
             },
             Instruction::SkipNe(x, y) => {
                 // Skip instruction if Vx != Vy
                 if self.registers[x] != self.registers[y] {
-                    increase_mem_pos += 2;
+                    increase_mem_pos += self.skip_len();
                 }
             },
             Instruction::SkipPressed(x) => {
                 // Skip instruction if Vx == pressed key
-                if pressed_keys[self.registers[x] as usize] {
-                    increase_mem_pos += 2;
+                if pressed_keys[self.registers[x] as usize & 0xF] {
+                    increase_mem_pos += self.skip_len();
                 }
             },
             Instruction::SkipNPressed(x) => {
                 // Skip instruction if Vx != pressed key
-                if !pressed_keys[self.registers[x] as usize] {
-                    increase_mem_pos += 2;
+                if !pressed_keys[self.registers[x] as usize & 0xF] {
+                    increase_mem_pos += self.skip_len();
                 }
             },
 
@@ -182,7 +555,7 @@ impl Machine {
             },
             Instruction::LoadRandom(x, b) => {
                 // Vx = random() & b
-                self.registers[x] = rand::random::<u8>() & b;
+                self.registers[x] = self.random.next() & b;
             },
             Instruction::AddByte(x, b) => {
                 // Vx = Vx + b
@@ -195,14 +568,23 @@ impl Machine {
             Instruction::Or(x, y) => {
                 // Vx = Vx | Vy
                 self.registers[x] |= self.registers[y];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
             },
             Instruction::And(x, y) => {
                 // Vx = Vx & Vy
                 self.registers[x] &= self.registers[y];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
             },
             Instruction::Xor(x, y) => {
                 // Vx = Vx ^ Vy
                 self.registers[x] ^= self.registers[y];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
             },
             Instruction::Add(x, y) => {
                 // Vx = Vx + Vy, VF = 1 of overflowed
@@ -222,15 +604,17 @@ impl Machine {
                 self.registers[x] = val;
                 self.registers[0xF] = !ovf as u8;
             },
-            Instruction::Shr(x) => {
-                // Vx = Vx SHR 1, VF = rightmost bit before SHR
-                self.registers[0xF] = self.registers[x] & 0x1;
-                self.registers[x] >>= 1;
+            Instruction::Shr(x, y) => {
+                // Vx = Vx SHR 1 (or Vy SHR 1), VF = rightmost bit before SHR
+                let val = self.registers[if self.quirks.shift_uses_vy { y } else { x }];
+                self.registers[0xF] = val & 0x1;
+                self.registers[x] = val >> 1;
             },
-            Instruction::Shl(x) => {
-                // Vx = Vx SHL 1, VF = leftmost bit before SHL
-                self.registers[0xF] = (self.registers[x] & 0x80) >> 7;
-                self.registers[x] <<= 1;
+            Instruction::Shl(x, y) => {
+                // Vx = Vx SHL 1 (or Vy SHL 1), VF = leftmost bit before SHL
+                let val = self.registers[if self.quirks.shift_uses_vy { y } else { x }];
+                self.registers[0xF] = (val & 0x80) >> 7;
+                self.registers[x] = val << 1;
             },
             Instruction::LoadDelay(x) => {
                 // Vx = DT
@@ -263,17 +647,28 @@ impl Machine {
             },
             Instruction::StoreBCD(x) => {
                 // Store BCD representation of a Vx in memory[I..I+2]
-                self.memory[self.i] = self.registers[x] / 100 % 10;
-                self.memory[self.i + 1] = self.registers[x] / 10 % 10;
-                self.memory[self.i + 2] = self.registers[x] % 10;
+                self.check_i(3)?;
+                let bcd = [
+                    self.registers[x] / 100 % 10,
+                    self.registers[x] / 10 % 10,
+                    self.registers[x] % 10,
+                ];
+                self.write_memory(self.i, &bcd);
             },
             Instruction::StoreRegisters(x) => {
                 // Store registers[0..x] in memory[i..i+x]
-                self.memory[self.i..=self.i + x].copy_from_slice(&self.registers[0..=x]);
+                self.check_i(x + 1)?;
+                let registers = self.registers;
+                self.write_memory(self.i, &registers[0..=x]);
+                self.increment_i(x);
             },
             Instruction::RestoreRegisters(x) => {
                 // Restore registers from memory[i..i+x] into reisters[0..x]
-                self.registers[0..=x].copy_from_slice(&self.memory[self.i..=self.i + x]);
+                self.check_i(x + 1)?;
+                let mut registers = self.registers;
+                self.read_memory(Access::Read, self.i, &mut registers[0..=x]);
+                self.registers = registers;
+                self.increment_i(x);
             },
 
             Instruction::Clear => {
@@ -282,18 +677,862 @@ impl Machine {
             },
             Instruction::Draw(x, y, n) => {
                 // Draw a sprite from memory[i..i+n] at (Vx, Vy), VF - collision
-                self.registers[0xF] = self.screen.draw(
+                // n = 0 draws a 16x16 sprite from memory[i..i+32]
+                // Every selected plane reads its own sprite data, one after another
+                let (len, width) = if n == 0 { (32, 16) } else { (n as usize, 8) };
+                let len = len * self.screen.plane_count();
+                self.check_i(len)?;
+                let mut sprite = [0; 64];
+                self.read_memory(Access::Read, self.i, &mut sprite[0..len]);
+                let collisions = self.screen.draw(
                     self.registers[x],
                     self.registers[y],
-                    &self.memory[self.i..self.i + n as usize],
-                ) as _;
+                    &sprite[0..len],
+                    width,
+                    self.quirks.clip_sprites,
+                );
+                self.registers[0xF] = if self.quirks.collision_rows && self.screen.hires() {
+                    let rows = len / self.screen.plane_count().max(1) / (width / 8);
+                    let bottom = self.registers[y] as usize % HEIGHT + rows;
+                    let clipped = if self.quirks.clip_sprites {
+                        bottom.saturating_sub(HEIGHT)
+                    } else {
+                        0
+                    };
+                    (collisions + clipped) as _
+                } else {
+                    (collisions > 0) as _
+                };
+            },
+
+            Instruction::ScrollDown(n) => {
+                // Scroll screen n pixels down
+                self.screen
+                    .scroll_down(n as usize, self.quirks.scroll_lowres_pixels);
+            },
+            Instruction::ScrollRight => {
+                // Scroll screen 4 pixels right
+                self.screen.scroll_right(self.quirks.scroll_lowres_pixels);
+            },
+            Instruction::ScrollLeft => {
+                // Scroll screen 4 pixels left
+                self.screen.scroll_left(self.quirks.scroll_lowres_pixels);
+            },
+            Instruction::LowRes => {
+                // Switch to 64x32 mode
+                self.screen.set_hires(false);
+            },
+            Instruction::HighRes => {
+                // Switch to 128x64 mode
+                self.screen.set_hires(true);
+            },
+            Instruction::Exit => {
+                // Stop the interpreter
+                self.halted = true;
+                increase_mem_pos = 0;
+            },
+            Instruction::SetBigSprite(x) => {
+                // I = location of a 8x10 sprite for a digit stored in Vx
+                self.i = BIG_SPRITES_POS + (self.registers[x] as usize % 0x10) * 10;
+            },
+            Instruction::StoreFlags(x) => {
+                // Store registers[0..x] in RPL flags
+                self.rpl[0..=x].copy_from_slice(&self.registers[0..=x]);
+            },
+            Instruction::RestoreFlags(x) => {
+                // Restore registers[0..x] from RPL flags
+                self.registers[0..=x].copy_from_slice(&self.rpl[0..=x]);
+            },
+
+            Instruction::SaveRange(x, y) => {
+                // Store registers[x..y] in memory[i..], in reverse order if x > y
+                let len = x.abs_diff(y) + 1;
+                self.check_i(len)?;
+                let mut data = [0; 16];
+                for (k, v) in data[0..len].iter_mut().enumerate() {
+                    *v = self.registers[if x <= y { x + k } else { x - k }];
+                }
+                self.write_memory(self.i, &data[0..len]);
+            },
+            Instruction::LoadRange(x, y) => {
+                // Restore registers[x..y] from memory[i..], in reverse order if x > y
+                let len = x.abs_diff(y) + 1;
+                self.check_i(len)?;
+                let mut data = [0; 16];
+                self.read_memory(Access::Read, self.i, &mut data[0..len]);
+                for (k, v) in data[0..len].iter().enumerate() {
+                    self.registers[if x <= y { x + k } else { x - k }] = *v;
+                }
+            },
+            Instruction::LongLoadI => {
+                // I = next 2 bytes
+                if self.memory_pos + 3 >= self.memory.len() {
+                    return Err(MachineError::PcOutOfRange {
+                        address: self.memory_pos + 2,
+                    });
+                }
+                let mut addr = [0; 2];
+                self.read_memory(Access::Execute, self.memory_pos + 2, &mut addr);
+                self.i = (addr[0] as usize) << 8 | addr[1] as usize;
+                increase_mem_pos = 4;
+            },
+            Instruction::Plane(n) => {
+                // Select bitplanes n
+                self.screen.set_planes(n);
+            },
+            Instruction::LoadAudio => {
+                // Load audio pattern buffer from memory[i..i+16]
+                self.check_i(16)?;
+                let mut pattern = [0; 16];
+                self.read_memory(Access::Read, self.i, &mut pattern);
+                self.audio_pattern = pattern;
+            },
+            Instruction::SetPitch(x) => {
+                // Pitch = Vx
+                self.pitch = self.registers[x];
             },
         }
 
         self.memory_pos += increase_mem_pos;
+
+        Ok(())
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+
+        state.u32(self.memory.len());
+        state.bytes(&self.memory);
+        state.u32(self.memory_pos);
+
+        for addr in self.stack.iter() {
+            state.u32(*addr);
+        }
+        state.u8(self.stack_pos as _);
+
+        state.bytes(&self.registers);
+        state.u32(self.i);
+        state.u8(self.delay);
+        state.u8(self.sound);
+
+        match self.load_key {
+            LoadKeyState::None => state.bytes(&[0, 0, 0]),
+            LoadKeyState::WaitPress { reg } => state.bytes(&[1, reg as _, 0]),
+            LoadKeyState::WaitRelease { reg, key } => state.bytes(&[2, reg as _, key as _]),
+        }
+        state.bytes(&self.rpl);
+        state.bytes(&self.audio_pattern);
+        state.u8(self.pitch);
+        state.bool(self.halted);
+        state.bytes(&self.random.to_bytes());
+        state.u32(self.cycles as usize);
+        state.bool(self.vblank);
+
+        self.screen.save_state(&mut state);
+
+        state.finish()
+    }
+
+    /// Restores a state produced by `save_state`, the machine is left untouched on error
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data)?;
+        let mut machine = Self::new(self.quirks, &[]);
+
+        let memory_len = state.u32()?;
+        if memory_len != machine.memory.len() {
+            return Err(StateError::MemorySizeMismatch {
+                expected: machine.memory.len(),
+                found: memory_len,
+            });
+        }
+        machine.memory.copy_from_slice(state.bytes(memory_len)?);
+        machine.memory_pos = state.u32()?;
+
+        for addr in machine.stack.iter_mut() {
+            *addr = state.u32()?;
+        }
+        machine.stack_pos = state.u8()? as _;
+        if machine.stack_pos > machine.stack.len() {
+            return Err(StateError::InvalidValue);
+        }
+
+        machine.registers.copy_from_slice(state.bytes(16)?);
+        machine.i = state.u32()?;
+        machine.delay = state.u8()?;
+        machine.sound = state.u8()?;
+
+        let load_key = state.bytes(3)?;
+        let (reg, key) = (load_key[1] as usize, load_key[2] as usize);
+        if reg >= 16 || key >= 16 {
+            return Err(StateError::InvalidValue);
+        }
+        machine.load_key = match load_key[0] {
+            0 => LoadKeyState::None,
+            1 => LoadKeyState::WaitPress { reg },
+            2 => LoadKeyState::WaitRelease { reg, key },
+            _ => return Err(StateError::InvalidValue),
+        };
+        machine.rpl.copy_from_slice(state.bytes(16)?);
+        machine.audio_pattern.copy_from_slice(state.bytes(16)?);
+        machine.pitch = state.u8()?;
+        machine.halted = state.bool()?;
+        machine.random = Random::from_bytes(state.bytes(9)?).ok_or(StateError::InvalidValue)?;
+        machine.cycles = state.u32()? as u32;
+        machine.vblank = state.bool()?;
+
+        machine.screen.load_state(&mut state)?;
+
+        machine.watchpoints = std::mem::take(&mut self.watchpoints);
+        machine.tracer = self.tracer.take();
+        *self = machine;
+        Ok(())
+    }
+
+    pub fn pc(&self) -> usize {
+        self.memory_pos
+    }
+    pub fn registers(&self) -> [u8; 16] {
+        self.registers
+    }
+    pub fn i(&self) -> usize {
+        self.i
+    }
+    pub fn delay(&self) -> u8 {
+        self.delay
+    }
+    pub fn sound(&self) -> u8 {
+        self.sound
+    }
+    /// What plays while the sound timer is active, the XO-CHIP pattern once a ROM loaded one
+    pub fn tone(&self) -> Option<Tone> {
+        if self.sound == 0 {
+            None
+        } else if self.audio_pattern == [0; 16] {
+            Some(Tone::Beep)
+        } else {
+            Some(Tone::Pattern(self.audio_pattern, self.pitch))
+        }
+    }
+    pub fn stack(&self) -> &[usize] {
+        &self.stack[0..self.stack_pos]
+    }
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Setters for debuggers, they bypass watchpoints
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.registers[x] = value;
+    }
+    pub fn set_i(&mut self, i: usize) {
+        self.i = i;
+    }
+    pub fn set_pc(&mut self, pc: usize) {
+        self.memory_pos = pc;
+    }
+    pub fn set_stack_len(&mut self, len: usize) {
+        self.stack_pos = len.min(self.stack.len());
+    }
+    pub fn set_delay(&mut self, delay: u8) {
+        self.delay = delay;
+    }
+    pub fn set_sound(&mut self, sound: u8) {
+        self.sound = sound;
+    }
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// Instruction at the program counter, `None` if it is invalid or out of memory
+    pub fn current_instruction(&self) -> Option<Instruction> {
+        let opcode = self.memory.get(self.memory_pos..self.memory_pos + 2)?;
+        Instruction::parse(opcode[0], opcode[1])
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.retain(|w| *w != watchpoint);
+    }
+    pub fn remove_watchpoints(&mut self, start: usize) {
+        self.watchpoints.retain(|w| w.start != start);
+    }
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+    /// Returns the watchpoint hits since the last call
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    /// Whether the keypad was read since the last call, frames where it wasn't are lag frames
+    pub fn take_input_polled(&mut self) -> bool {
+        std::mem::take(&mut self.input_polled)
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn rpl_flags(&self) -> [u8; 16] {
+        self.rpl
+    }
+    pub fn set_rpl_flags(&mut self, rpl: [u8; 16]) {
+        self.rpl = rpl;
+    }
+
+    pub fn set_random(&mut self, random: Random) {
+        self.random = random;
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) {
+        self.tracer = tracer;
     }
 
     pub fn screen(&mut self) -> &mut Screen {
         &mut self.screen
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `program` until it crashes, or for at most 100 instructions
+    fn crash(program: &[u8]) -> Option<MachineError> {
+        let mut machine = Machine::new(Quirks::default(), program);
+        (0..100).find_map(|_| machine.step([false; 16]).err())
+    }
+
+    #[test]
+    fn pc_out_of_range() {
+        assert!(matches!(
+            crash(&[0x1F, 0xFF]),
+            Some(MachineError::PcOutOfRange { address: 0xFFF })
+        ));
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        assert!(matches!(
+            crash(&[0x50, 0x01]),
+            Some(MachineError::InvalidOpcode {
+                address: 0x200,
+                opcode: [0x50, 0x01],
+            })
+        ));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        assert!(matches!(
+            crash(&[0x22, 0x00]),
+            Some(MachineError::StackOverflow { address: 0x200 })
+        ));
+    }
+
+    #[test]
+    fn stack_underflow() {
+        assert!(matches!(
+            crash(&[0x00, 0xEE]),
+            Some(MachineError::StackUnderflow { address: 0x200 })
+        ));
+    }
+
+    #[test]
+    fn i_out_of_bounds() {
+        assert!(matches!(
+            crash(&[0xAF, 0xFF, 0xF1, 0x65]),
+            Some(MachineError::IOutOfBounds {
+                address: 0x202,
+                i: 0xFFF,
+                len: 2,
+            })
+        ));
+    }
+
+    #[test]
+    fn skip_key_above_f() {
+        // V0 = 0x1F, key F is checked, so the jump to 0x206 is skipped
+        let program = [0x60, 0x1F, 0xE0, 0x9E, 0xE0, 0xA1, 0x12, 0x06, 0x12, 0x08];
+        let mut machine = Machine::new(Quirks::default(), &program);
+        let mut keys = [false; 16];
+        keys[0xF] = true;
+        for _ in 0..3 {
+            machine.step(keys).unwrap();
+        }
+        assert_eq!(machine.pc(), 0x206);
+    }
+
+    #[test]
+    fn state_round_trips() {
+        // Draws, calls a subroutine and waits for a key in it
+        let program = [
+            0x60, 0x05, 0xF0, 0x29, 0xD1, 0x25, 0xC3, 0xFF, 0x22, 0x0C, 0x12, 0x0A, 0xF4, 0x0A,
+        ];
+        let mut machine = Machine::new(Quirks::default(), &program);
+        machine.set_random(Random::new(1, false));
+        machine.set_rpl_flags([7; 16]);
+        for _ in 0..10 {
+            machine.step([false; 16]).unwrap();
+        }
+        machine.tick_timers();
+        let state = machine.save_state();
+
+        let mut loaded = Machine::new(Quirks::default(), &[]);
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+        assert_eq!(loaded.pc(), machine.pc());
+        assert_eq!(loaded.stack(), machine.stack());
+        assert_eq!(loaded.registers(), machine.registers());
+        assert_eq!(loaded.i(), machine.i());
+        assert_eq!(loaded.rpl_flags(), [7; 16]);
+        assert_eq!(loaded.screen().buffer(), machine.screen().buffer());
+
+        // Both go on the same way
+        let mut keys = [false; 16];
+        keys[3] = true;
+        for machine in [&mut machine, &mut loaded].iter_mut() {
+            for _ in 0..3 {
+                machine.step(keys).unwrap();
+            }
+        }
+        assert_eq!(loaded.save_state(), machine.save_state());
+    }
+
+    #[test]
+    fn state_version_is_checked() {
+        let mut state = Machine::new(Quirks::default(), &[]).save_state();
+        state[4] = crate::state::VERSION - 1;
+        let mut machine = Machine::new(Quirks::default(), &[]);
+        assert!(matches!(
+            machine.load_state(&state),
+            Err(StateError::UnsupportedVersion(v)) if v == crate::state::VERSION - 1
+        ));
+    }
+
+    /// Runs `steps` instructions of `program` watching `watchpoint`
+    fn watch_hits(
+        quirks: Quirks,
+        program: &[u8],
+        watchpoint: Watchpoint,
+        steps: usize,
+    ) -> Vec<usize> {
+        let mut machine = Machine::new(quirks, program);
+        machine.add_watchpoint(watchpoint);
+        for _ in 0..steps {
+            machine.step([false; 16]).unwrap();
+        }
+        machine
+            .take_watch_hits()
+            .iter()
+            .map(|hit| hit.address)
+            .collect()
+    }
+
+    fn watchpoint(access: Access, start: usize, end: usize) -> Watchpoint {
+        Watchpoint { access, start, end }
+    }
+
+    #[test]
+    fn watch_reads() {
+        // LD I, 0x2FE; LD V1, [I]
+        let program = [0xA2, 0xFE, 0xF1, 0x65];
+        let read = watchpoint(Access::Read, 0x2FF, 0x310);
+        assert_eq!(watch_hits(Quirks::default(), &program, read, 2), [0x2FF]);
+        let write = watchpoint(Access::Write, 0x2FF, 0x310);
+        assert_eq!(watch_hits(Quirks::default(), &program, write, 2), []);
+        let before = watchpoint(Access::Read, 0x200, 0x2FD);
+        assert_eq!(watch_hits(Quirks::default(), &program, before, 2), []);
+    }
+
+    #[test]
+    fn watch_writes() {
+        // LD I, 0x305; LD [I], V1
+        let program = [0xA3, 0x05, 0xF1, 0x55];
+        let write = watchpoint(Access::Write, 0x300, 0x310);
+        assert_eq!(watch_hits(Quirks::default(), &program, write, 2), [0x305]);
+        let read = watchpoint(Access::Read, 0x300, 0x310);
+        assert_eq!(watch_hits(Quirks::default(), &program, read, 2), []);
+    }
+
+    #[test]
+    fn watch_execution() {
+        // LD V0, 0; LD V0, 1; JP 0x202
+        let program = [0x60, 0x00, 0x60, 0x01, 0x12, 0x02];
+        let execute = watchpoint(Access::Execute, 0x202, 0x203);
+        assert_eq!(
+            watch_hits(Quirks::default(), &program, execute, 5),
+            [0x202, 0x202]
+        );
+    }
+
+    #[test]
+    fn watch_ignores_empty_reads() {
+        // PLANE 0; LD I, 0x305; DRW V0, V0, 1
+        let program = [0xF0, 0x01, 0xA3, 0x05, 0xD0, 0x01];
+        let read = watchpoint(Access::Read, 0x300, 0x310);
+        assert_eq!(watch_hits(Quirks::XO_CHIP, &program, read, 3), []);
+        let read = watchpoint(Access::Read, 0x000, 0x310);
+        assert_eq!(watch_hits(Quirks::XO_CHIP, &program, read, 3), []);
+    }
+
+    /// Runs `steps` instructions of `program` with no key pressed
+    fn run(quirks: Quirks, program: &[u8], steps: usize) -> Machine {
+        let mut machine = Machine::new(quirks, program);
+        for _ in 0..steps {
+            machine.step([false; 16]).unwrap();
+        }
+        machine
+    }
+
+    #[test]
+    fn xo_chip_register_ranges() {
+        let program = [
+            0x61, 0x01, 0x62, 0x02, 0x63, 0x03, // V1..V3 = 1, 2, 3
+            0xA3, 0x00, 0x51, 0x32, // SAVE V1, V3 at 0x300
+            0xA3, 0x10, 0x53, 0x12, // SAVE V3, V1 at 0x310, reversed
+            0x54, 0x63, // LOAD V4, V6 from 0x310
+            0x5F, 0xE3, // LOAD VF, VE from 0x310, reversed
+        ];
+        let machine = run(Quirks::XO_CHIP, &program, 9);
+        assert_eq!(machine.memory()[0x300..0x303], [1, 2, 3]);
+        assert_eq!(machine.memory()[0x310..0x313], [3, 2, 1]);
+        assert_eq!(machine.registers()[4..7], [3, 2, 1]);
+        assert_eq!(machine.registers()[0xE..], [2, 3]);
+        // I is left as is
+        assert_eq!(machine.i(), 0x310);
+    }
+
+    #[test]
+    fn xo_chip_long_load_i() {
+        let program = [
+            0xF0, 0x00, 0x12, 0x34, // LD I, 0x1234
+            0x30, 0x00, // SE V0, 0 skips all 4 bytes of the next one
+            0xF0, 0x00, 0xFF, 0xFF, // LD I, 0xFFFF
+            0x60, 0x07, // LD V0, 7
+        ];
+        let machine = run(Quirks::XO_CHIP, &program, 3);
+        assert_eq!(machine.i(), 0x1234);
+        assert_eq!(machine.registers()[0], 7);
+        assert_eq!(machine.pc(), 0x20C);
+    }
+
+    #[test]
+    fn xo_chip_planes() {
+        let program = [
+            0xF3, 0x01, // PLANE 3
+            0xA2, 0x0C, 0xD0, 0x01, // DRW V0, V0, 1 with a row for each plane
+            0xF2, 0x01, // PLANE 2
+            0x00, 0xE0, // CLS
+            0x12, 0x0A, // JP 0x20A
+            0x80, 0xC0,
+        ];
+        let mut machine = run(Quirks::XO_CHIP, &program, 3);
+        assert_eq!(machine.screen().buffer()[0][..4], [3, 3, 2, 2]);
+        for _ in 0..2 {
+            machine.step([false; 16]).unwrap();
+        }
+        assert_eq!(machine.screen().buffer()[0][..4], [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn xo_chip_audio_pattern() {
+        let mut program = vec![
+            0x60, 0x05, // LD V0, 5
+            0xA2, 0x0C, // LD I, 0x20C
+            0xF0, 0x02, // AUDIO
+            0xF0, 0x18, // LD ST, V0
+            0xF0, 0x3A, // PITCH V0
+            0x12, 0x0A, // JP 0x20A
+        ];
+        let pattern: Vec<u8> = (0..16).map(|i| i * 17).collect();
+        program.extend_from_slice(&pattern);
+
+        let mut machine = run(Quirks::XO_CHIP, &program, 4);
+        let mut expected = [0; 16];
+        expected.copy_from_slice(&pattern);
+        assert_eq!(machine.tone(), Some(Tone::Pattern(expected, 64)));
+        machine.step([false; 16]).unwrap();
+        assert_eq!(machine.tone(), Some(Tone::Pattern(expected, 5)));
+    }
+
+    #[test]
+    fn lowres_scrolling_quirk() {
+        // DRW V0, V0, 1 of the font's top row of 0, then SCD 1
+        let program = [0xD0, 0x01, 0x00, 0xC1];
+        let mut machine = run(Quirks::SUPER_CHIP, &program, 2);
+        assert_eq!(machine.screen().buffer()[1][0], 1);
+        assert_eq!(machine.screen().buffer()[3][0], 0);
+        let mut machine = run(Quirks::XO_CHIP, &program, 2);
+        assert_eq!(machine.screen().buffer()[1][0], 0);
+        assert_eq!(machine.screen().buffer()[3][0], 1);
+    }
+
+    #[test]
+    fn shift_quirk() {
+        // LD V1, 3; SHR V0, V1
+        let program = [0x61, 0x03, 0x80, 0x16];
+        let machine = run(Quirks::COSMAC_VIP, &program, 2);
+        assert_eq!((machine.registers()[0], machine.registers()[0xF]), (1, 1));
+        let machine = run(Quirks::SUPER_CHIP, &program, 2);
+        assert_eq!((machine.registers()[0], machine.registers()[0xF]), (0, 0));
+    }
+
+    #[test]
+    fn index_increment_quirk() {
+        // LD I, 0x300; LD [I], V2
+        let program = [0xA3, 0x00, 0xF2, 0x55];
+        assert_eq!(run(Quirks::COSMAC_VIP, &program, 2).i(), 0x303);
+        assert_eq!(run(Quirks::CHIP_48, &program, 2).i(), 0x302);
+        assert_eq!(run(Quirks::SUPER_CHIP, &program, 2).i(), 0x300);
+    }
+
+    #[test]
+    fn jump_quirk() {
+        // LD V0, 4; LD V2, 8; JP V2, 0x220
+        let program = [0x60, 0x04, 0x62, 0x08, 0xB2, 0x20];
+        assert_eq!(run(Quirks::SUPER_CHIP, &program, 3).pc(), 0x228);
+        assert_eq!(run(Quirks::COSMAC_VIP, &program, 3).pc(), 0x224);
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        // LD VF, 5; OR V0, V1
+        let program = [0x6F, 0x05, 0x80, 0x11];
+        assert_eq!(run(Quirks::COSMAC_VIP, &program, 2).registers()[0xF], 0);
+        assert_eq!(run(Quirks::SUPER_CHIP, &program, 2).registers()[0xF], 5);
+    }
+
+    #[test]
+    fn clipping_quirk() {
+        // LD V0, 62; DRW V0, V1, 1 of the font's top row of 0, 4 pixels wide
+        let program = [0x60, 0x3E, 0xD0, 0x11];
+        let mut machine = run(Quirks::COSMAC_VIP, &program, 2);
+        assert_eq!(machine.screen().buffer()[0][124..], [1, 1, 1, 1]);
+        assert_eq!(machine.screen().buffer()[0][..4], [0, 0, 0, 0]);
+        let mut machine = run(Quirks::XO_CHIP, &program, 2);
+        assert_eq!(machine.screen().buffer()[0][124..], [1, 1, 1, 1]);
+        assert_eq!(machine.screen().buffer()[0][..4], [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        // Two draws, only one of them in a frame of VIP timing
+        let program = [0xD0, 0x01, 0xD0, 0x01, 0x12, 0x04];
+        let mut machine = Machine::new(Quirks::COSMAC_VIP, &program);
+        machine.run_vip_frame([false; 16]).unwrap();
+        assert_eq!(machine.pc(), 0x202);
+        machine.run_vip_frame([false; 16]).unwrap();
+        assert_eq!(machine.pc(), 0x204);
+
+        let mut machine = Machine::new(Quirks::COSMAC_VIP, &program);
+        machine.run_frame(2, [false; 16]).unwrap();
+        assert_eq!(machine.pc(), 0x204);
+    }
+
+    #[test]
+    fn schip_scrolling() {
+        let program = [
+            0x00, 0xFF, // HIGH
+            0xD0, 0x01, // DRW V0, V0, 1 of the font's top row of 0
+            0x00, 0xC2, // SCD 2
+            0x00, 0xFB, // SCR
+            0x00, 0xFC, // SCL
+        ];
+        let mut machine = run(Quirks::SUPER_CHIP, &program, 3);
+        assert_eq!(machine.screen().buffer()[0][..4], [0, 0, 0, 0]);
+        assert_eq!(machine.screen().buffer()[2][..8], [1, 1, 1, 1, 0, 0, 0, 0]);
+        machine.step([false; 16]).unwrap();
+        assert_eq!(machine.screen().buffer()[2][..8], [0, 0, 0, 0, 1, 1, 1, 1]);
+        machine.step([false; 16]).unwrap();
+        assert_eq!(machine.screen().buffer()[2][..8], [1, 1, 1, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn schip_resolution() {
+        // HIGH; DRW V0, V0, 1; LOW; DRW V0, V0, 1
+        let program = [0x00, 0xFF, 0xD0, 0x01, 0x00, 0xFE, 0xD0, 0x01];
+        let mut machine = run(Quirks::SUPER_CHIP, &program, 2);
+        assert!(machine.screen().hires());
+        assert_eq!(machine.screen().buffer()[0][..5], [1, 1, 1, 1, 0]);
+        assert_eq!(machine.screen().buffer()[1][..5], [0, 0, 0, 0, 0]);
+
+        // Switching clears the screen, low-res pixels are 2x2
+        for _ in 0..2 {
+            machine.step([false; 16]).unwrap();
+        }
+        assert!(!machine.screen().hires());
+        for row in 0..2 {
+            assert_eq!(
+                machine.screen().buffer()[row][..9],
+                [1, 1, 1, 1, 1, 1, 1, 1, 0]
+            );
+        }
+    }
+
+    #[test]
+    fn schip_big_sprites() {
+        let mut program = vec![
+            0x00, 0xFF, // HIGH
+            0xA2, 0x10, // LD I, 0x210
+            0xD0, 0x10, // DRW V0, V1, 0
+            0xD0, 0x10, // DRW V0, V1, 0 again
+            0x61, 0x38, // LD V1, 56
+            0x00, 0xE0, // CLS
+            0xD0, 0x10, // DRW V0, V1, 0 half off the bottom
+            0x12, 0x0E, // JP 0x20E
+        ];
+        program.extend_from_slice(&[0xFF; 32]);
+
+        let mut machine = run(Quirks::SUPER_CHIP, &program, 3);
+        assert_eq!(machine.registers()[0xF], 0);
+        let buffer = machine.screen().buffer();
+        assert!(buffer[..16].iter().all(|row| row[..16] == [1; 16]));
+        assert_eq!((buffer[0][16], buffer[16][0]), (0, 0));
+
+        // Every row collides, then the 8 rows off the screen count
+        machine.step([false; 16]).unwrap();
+        assert_eq!(machine.registers()[0xF], 16);
+        for _ in 0..3 {
+            machine.step([false; 16]).unwrap();
+        }
+        assert_eq!(machine.registers()[0xF], 8);
+
+        // Only a collision counts, once
+        let mut machine = run(Quirks::XO_CHIP, &program, 4);
+        assert_eq!(machine.registers()[0xF], 1);
+        for _ in 0..3 {
+            machine.step([false; 16]).unwrap();
+        }
+        assert_eq!(machine.registers()[0xF], 0);
+
+        // And in low-res mode
+        program[..2].copy_from_slice(&[0x00, 0xFE]);
+        let machine = run(Quirks::SUPER_CHIP, &program, 4);
+        assert_eq!(machine.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn schip_big_font() {
+        // LD V0, 3; LD HF, V0
+        let program = [0x60, 0x03, 0xF0, 0x30];
+        let machine = run(Quirks::SUPER_CHIP, &program, 2);
+        assert_eq!(machine.i(), BIG_SPRITES_POS + 30);
+        assert_eq!(machine.memory()[machine.i()..][..10], BIG_SPRITES[30..40]);
+    }
+
+    #[test]
+    fn schip_rpl_flags() {
+        let program = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, // V0..V2 = 1, 2, 3
+            0xF2, 0x75, // LD R, V2
+            0x60, 0x00, 0x61, 0x00, 0x62, 0x00, // V0..V2 = 0
+            0xF1, 0x85, // LD V1, R
+        ];
+        let machine = run(Quirks::SUPER_CHIP, &program, 4);
+        assert_eq!(machine.rpl_flags()[..4], [1, 2, 3, 0]);
+        let machine = run(Quirks::SUPER_CHIP, &program, 8);
+        assert_eq!(machine.registers()[..3], [1, 2, 0]);
+    }
+
+    /// VIP machine cycles charged for each of the first `steps` instructions of `program`
+    fn vip_costs(program: &[u8], steps: usize) -> Vec<u32> {
+        let mut machine = Machine::new(Quirks::COSMAC_VIP, program);
+        (0..steps)
+            .map(|_| {
+                let before = machine.cycles;
+                machine.vip_step([false; 16]).unwrap();
+                machine.cycles - before
+            })
+            .collect()
+    }
+
+    #[test]
+    fn vip_cycles() {
+        let program = [
+            0x60, 0x00, // LD V0, 0
+            0x30, 0x00, // SE V0, 0 skips
+            0x00, 0x00, //
+            0x30, 0x01, // SE V0, 1
+            0xA2, 0xFF, // LD I, 0x2FF
+            0x61, 0x01, // LD V1, 1
+            0xF1, 0x1E, // ADD I, V1 to the next page
+            0xD0, 0x03, // DRW V0, V0, 3 aligned to a byte
+            0x60, 0x03, // LD V0, 3
+            0xD0, 0x03, // DRW V0, V0, 3 over two bytes
+            0xF0, 0x33, // LD B, V0
+        ];
+        let fetch = VIP_FETCH_CYCLES;
+        assert_eq!(
+            vip_costs(&program, 10),
+            [
+                fetch + 6,
+                fetch + 10 + 4,
+                fetch + 10,
+                fetch + 12,
+                fetch + 6,
+                fetch + 16 + 2,
+                fetch + 26 + 46 * 3,
+                fetch + 6,
+                fetch + 26 + 68 * 3,
+                fetch + 80 + 16 * 3,
+            ]
+        );
+    }
+
+    #[test]
+    fn vip_frame_budget() {
+        // JP 0x200, 52 cycles, the rest of a frame carries over to the next one
+        let mut machine = Machine::new(Quirks::COSMAC_VIP, &[0x12, 0x00]);
+        let mut carried = 0;
+        for _ in 0..3 {
+            let mut steps = 0;
+            while !machine.vip_frame_done() {
+                machine.vip_step([false; 16]).unwrap();
+                steps += 1;
+            }
+            assert_eq!(steps, (VIP_FRAME_BUDGET - carried).div_ceil(52));
+            assert_eq!(machine.cycles, carried + steps * 52);
+            machine.tick_timers();
+            carried = machine.cycles;
+            assert!(carried < 52);
+        }
+    }
+
+    #[test]
+    fn vip_draw_waits_for_vblank() {
+        // DRW V0, V0, 1; DRW V0, V0, 1
+        let mut machine = Machine::new(Quirks::COSMAC_VIP, &[0xD0, 0x01, 0xD0, 0x01]);
+        assert!(!machine.vip_frame_done());
+        machine.vip_step([false; 16]).unwrap();
+        assert!(machine.vip_frame_done());
+        assert!(machine.cycles < VIP_FRAME_BUDGET);
+
+        // Until the display interrupt
+        machine.tick_timers();
+        assert!(!machine.vip_frame_done());
+        machine.vip_step([false; 16]).unwrap();
+        assert_eq!(machine.pc(), 0x204);
+    }
+
+    #[test]
+    fn vip_timers_tick_once_per_frame() {
+        // LD V0, 5; LD DT, V0; LD ST, V0; JP 0x206
+        let program = [0x60, 0x05, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06];
+        let mut machine = Machine::new(Quirks::COSMAC_VIP, &program);
+        let mut timers = Vec::new();
+        for _ in 0..7 {
+            machine.run_vip_frame([false; 16]).unwrap();
+            timers.push((machine.delay(), machine.sound()));
+        }
+        assert_eq!(
+            timers,
+            [(4, 4), (3, 3), (2, 2), (1, 1), (0, 0), (0, 0), (0, 0)]
+        );
+    }
+}
@@ -1,34 +1,607 @@
 #[macro_use]
 extern crate clap; // clap is still not rust 2018 compatible
 
+mod asm;
+mod capture;
+mod debugger;
+mod disasm;
 mod frontends;
+mod gdb;
 mod instruction;
+mod keymap;
 mod machine;
+mod movie;
+mod quirks;
+mod random;
+mod rewind;
+mod runner;
 mod screen;
+mod sound;
+mod state;
+mod trace;
 
 fn main() {
     let mut app = clap::app_from_crate!()
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
         .arg(clap::Arg::with_name("file").required(true).help("ROM file"))
         .arg(
-            clap::Arg::with_name("freq")
+            clap::Arg::with_name("ipf")
                 .short("f")
+                .long("ipf")
                 .default_value("10")
-                .help("clock frequency (60hz * this) [max: 255]"),
+                .help("instructions per 60hz frame"),
+        )
+        .arg(
+            clap::Arg::with_name("vip-timing")
+                .long("vip-timing")
+                .conflicts_with("ipf")
+                .help("charge COSMAC VIP machine cycles per instruction instead of -f"),
+        )
+        .arg(
+            clap::Arg::with_name("quirks")
+                .long("quirks")
+                .takes_value(true)
+                .possible_values(&quirks::Quirks::PRESETS)
+                .help("interpretation of the ambiguous opcodes"),
+        )
+        .arg(
+            clap::Arg::with_name("rewind")
+                .long("rewind")
+                .default_value("10")
+                .help("seconds of history kept for rewinding"),
+        )
+        .arg(
+            clap::Arg::with_name("debug")
+                .short("d")
+                .long("debug")
+                .help("start paused with a debugger reading commands from stdin"),
+        )
+        .arg(
+            clap::Arg::with_name("gdb")
+                .long("gdb")
+                .takes_value(true)
+                .value_name("port")
+                .help("start paused with a GDB remote protocol server on localhost:port"),
+        )
+        .arg(
+            clap::Arg::with_name("frontend")
+                .long("frontend")
+                .default_value("gl")
+                .possible_values(&frontends::NAMES)
+                .help("where to show the screen, headless dumps it at the end"),
+        )
+        .arg(
+            clap::Arg::with_name("frames")
+                .long("frames")
+                .takes_value(true)
+                .help("stop after this many 60hz frames"),
+        )
+        .arg(
+            clap::Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .help("stop once pc=<addr>, v<x>=<value> or [<addr>]=<value>"),
+        )
+        .arg(
+            clap::Arg::with_name("fast")
+                .long("fast")
+                .help("run as fast as possible"),
+        )
+        .arg(
+            clap::Arg::with_name("input")
+                .long("input")
+                .takes_value(true)
+                .help("headless: script of '<frame> <hex keys>' lines pressing keys"),
+        )
+        .arg(
+            clap::Arg::with_name("dump")
+                .long("dump")
+                .takes_value(true)
+                .help("headless: write the screen to this file instead of stdout"),
+        )
+        .arg(
+            clap::Arg::with_name("png")
+                .long("png")
+                .takes_value(true)
+                .help("headless: write the final screen to this PNG file"),
+        )
+        .arg(
+            clap::Arg::with_name("wav")
+                .long("wav")
+                .takes_value(true)
+                .help("headless: record the sound to this WAV file"),
+        )
+        .arg(
+            clap::Arg::with_name("volume")
+                .long("volume")
+                .default_value("50")
+                .help("sound volume [max: 100]"),
+        )
+        .arg(
+            clap::Arg::with_name("tone")
+                .long("tone")
+                .default_value("440")
+                .help("frequency of the beep in Hz"),
+        )
+        .arg(
+            clap::Arg::with_name("waveform")
+                .long("waveform")
+                .default_value("square")
+                .possible_values(&sound::Waveform::NAMES)
+                .help("shape of the beep"),
+        )
+        .arg(
+            clap::Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .help("seed of the random number generator [default: random]"),
+        )
+        .arg(
+            clap::Arg::with_name("timed-random")
+                .long("timed-random")
+                .help("make random numbers depend on when they are asked for"),
+        )
+        .arg(
+            clap::Arg::with_name("trace")
+                .long("trace")
+                .takes_value(true)
+                .value_name("sink")
+                .help(
+                    "log executed instructions to stderr, file:<path> or ring:<count>, the ring \
+                       buffer is printed on crash",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("trace-addr")
+                .long("trace-addr")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("trace")
+                .value_name("range")
+                .help("only trace instructions at these addresses, like 0x200-0x2FF"),
+        )
+        .arg(
+            clap::Arg::with_name("trace-op")
+                .long("trace-op")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("trace")
+                .value_name("pattern")
+                .help("only trace these opcodes, like Dxyn or 8xy4"),
+        )
+        .arg(
+            clap::Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .value_name("movie")
+                .help("record the input to this movie file"),
+        )
+        .arg(
+            clap::Arg::with_name("play")
+                .long("play")
+                .takes_value(true)
+                .value_name("movie")
+                .conflicts_with("record")
+                .help("replay a movie file with its quirks, speed and seed"),
+        )
+        .arg(
+            clap::Arg::with_name("gif")
+                .long("gif")
+                .takes_value(true)
+                .help(
+                    "record the screen to this GIF file, screens shown for less than 2/100 s are \
+                     skipped",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("scale")
+                .long("scale")
+                .default_value("4")
+                .help("size of the screen pixels in screenshots and GIFs"),
+        )
+        .arg(
+            clap::Arg::with_name("palette")
+                .long("palette")
+                .default_value("000000,ffffff,555555,aaaaaa")
+                .help("colors of the bitplane combinations in screenshots and GIFs"),
+        )
+        .arg(
+            clap::Arg::with_name("keymap")
+                .long("keymap")
+                .takes_value(true)
+                .help("keymap file [default: ~/.config/chip8/keymap.toml if it exists]"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("disasm")
+                .about("disassemble a ROM")
+                .arg(clap::Arg::with_name("file").required(true).help("ROM file")),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("asm")
+                .about("assemble a ROM")
+                .arg(
+                    clap::Arg::with_name("file")
+                        .required(true)
+                        .help("source file"),
+                )
+                .arg(
+                    clap::Arg::with_name("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("ROM file [default: source file with the .ch8 extension]"),
+                ),
         );
 
     let matches = app.clone().get_matches();
 
-    let freq = matches.value_of("freq").unwrap().parse::<u8>();
-    if freq.is_err() {
-        println!("Error: invalid freq\n");
+    if let ("disasm", Some(matches)) = matches.subcommand() {
+        let file = matches.value_of("file").unwrap();
+        match std::fs::read(file) {
+            Ok(rom) => print!("{}", disasm::disassemble(&rom)),
+            Err(e) => println!("Error: could not read {}: {}", file, e),
+        }
+        return;
+    }
+
+    if let ("asm", Some(matches)) = matches.subcommand() {
+        let file = matches.value_of("file").unwrap();
+        let output = match matches.value_of("output") {
+            Some(output) => output.into(),
+            None => std::path::Path::new(file).with_extension("ch8"),
+        };
+        match asm::assemble_file(file) {
+            Ok(rom) => {
+                if let Err(e) = std::fs::write(&output, rom) {
+                    println!("Error: could not write {}: {}", output.display(), e);
+                }
+            },
+            Err(e) => println!("Error: {}", e),
+        }
+        return;
+    }
+
+    let ipf = matches.value_of("ipf").unwrap().parse::<u32>();
+    if !ipf.as_ref().is_ok_and(|ipf| *ipf > 0) {
+        println!("Error: invalid ipf\n");
+        app.print_help().unwrap();
+        println!();
+        return;
+    }
+    let ipf = ipf.unwrap();
+
+    let rewind = matches.value_of("rewind").unwrap().parse::<usize>();
+    if rewind.is_err() {
+        println!("Error: invalid rewind\n");
+        app.print_help().unwrap();
+        println!();
+        return;
+    }
+    let rewind = rewind::Rewind::new(rewind.unwrap() * 60);
+
+    let file = matches.value_of("file").unwrap();
+
+    let movie = match matches.value_of("play") {
+        Some(path) => match movie::Movie::load(path) {
+            Ok(movie) => Some(movie),
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            },
+        },
+        None => None,
+    };
+    let rom_hash = match std::fs::read(file) {
+        Ok(rom) => movie::hash(&rom),
+        Err(e) => {
+            println!("Error: could not read {}: {}", file, e);
+            return;
+        },
+    };
+    if movie
+        .as_ref()
+        .is_some_and(|m| m.header.rom_hash != rom_hash)
+    {
+        println!("Error: the movie was recorded with another ROM");
+        return;
+    }
+
+    let quirks = match &movie {
+        Some(movie) => movie.header.quirks,
+        None => matches
+            .value_of("quirks")
+            .and_then(quirks::Quirks::preset)
+            .unwrap_or_default(),
+    };
+
+    let mut machine = match machine::Machine::open(quirks, file) {
+        Ok(machine) => machine,
+        Err(e) => {
+            println!("Error: could not load {}: {}", file, e);
+            return;
+        },
+    };
+
+    // SUPER-CHIP RPL flags persist between runs of the same ROM
+    let rpl_path = format!("{}.rpl", file);
+    if let Some(movie) = &movie {
+        machine.set_rpl_flags(movie.header.rpl);
+    } else if let Ok(flags) = std::fs::read(&rpl_path) {
+        let mut rpl = [0; 16];
+        let len = flags.len().min(rpl.len());
+        rpl[..len].copy_from_slice(&flags[..len]);
+        machine.set_rpl_flags(rpl);
+    }
+
+    let seed = match (&movie, matches.value_of("seed").map(|s| s.parse::<u64>())) {
+        (Some(movie), _) => movie.header.seed,
+        (None, Some(Ok(seed))) => seed,
+        (None, Some(Err(_))) => {
+            println!("Error: invalid seed\n");
+            app.print_help().unwrap();
+            println!();
+            return;
+        },
+        (None, None) => rand::random(),
+    };
+    let timed_random = movie
+        .as_ref()
+        .map_or(matches.is_present("timed-random"), |m| {
+            m.header.timed_random
+        });
+    machine.set_random(random::Random::new(seed, timed_random));
+
+    if let Some(sink) = matches.value_of("trace") {
+        let sink = match trace::Sink::open(sink) {
+            Ok(sink) => sink,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            },
+        };
+
+        let mut filter = trace::Filter::default();
+        for ranges in matches.values_of("trace-addr").into_iter().flatten() {
+            if let Err(e) = filter.add_ranges(ranges) {
+                println!("Error: {}", e);
+                return;
+            }
+        }
+        for patterns in matches.values_of("trace-op").into_iter().flatten() {
+            if let Err(e) = filter.add_opcodes(patterns) {
+                println!("Error: {}", e);
+                return;
+            }
+        }
+
+        machine.set_tracer(Some(Box::new(trace::TraceLog::new(sink, filter))));
+    }
+
+    let gdb_port = matches.value_of("gdb").map(|p| p.parse::<u16>());
+    if let Some(Err(_)) = gdb_port {
+        println!("Error: invalid gdb port\n");
+        app.print_help().unwrap();
+        println!();
+        return;
+    }
+
+    let debugger = if matches.is_present("debug") || gdb_port.is_some() {
+        let (mut debugger, commands) = debugger::Debugger::new();
+
+        if let Some(Ok(port)) = gdb_port {
+            if let Err(e) = gdb::spawn(port, commands.clone(), debugger.subscribe()) {
+                println!("Error: could not start gdb server: {}", e);
+                return;
+            }
+            println!("Waiting for gdb on localhost:{}", port);
+        }
+
+        if matches.is_present("debug") {
+            debugger::spawn_repl(commands);
+            println!("Debugger started, type 'help' for the list of commands");
+        }
+
+        Some(debugger)
+    } else {
+        None
+    };
+
+    let frontend_name = matches.value_of("frontend").unwrap();
+    let headless_only = ["input", "dump", "png", "wav"];
+    if frontend_name != "headless" && headless_only.iter().any(|a| matches.is_present(a)) {
+        println!("Error: --input, --dump, --png and --wav need --frontend headless\n");
         app.print_help().unwrap();
         println!();
         return;
     }
-    let freq = freq.unwrap();
+    if frontend_name == "terminal" && matches.is_present("debug") {
+        println!("Error: the terminal frontend can't share stdin with the debugger\n");
+        app.print_help().unwrap();
+        println!();
+        return;
+    }
+
+    let mut runner = runner::Runner::new(ipf, file, rewind);
+    runner.debugger = debugger;
+    runner.fast = matches.is_present("fast");
+    runner.vip_timing = matches.is_present("vip-timing");
+
+    if let Some(movie) = movie {
+        runner.ipf = movie.header.ipf;
+        runner.vip_timing = movie.header.vip_timing;
+        runner.movie = Some(movie);
+        runner.movie_path = matches.value_of("play").map(Into::into);
+    } else if let Some(path) = matches.value_of("record") {
+        runner.movie = Some(movie::Movie::record(movie::Header {
+            rom_hash,
+            seed,
+            timed_random,
+            quirks,
+            ipf,
+            vip_timing: runner.vip_timing,
+            rpl: machine.rpl_flags(),
+        }));
+        runner.movie_path = Some(path.to_string());
+    }
+
+    if let Some(frames) = matches.value_of("frames") {
+        match frames.parse() {
+            Ok(frames) => runner.frames = Some(frames),
+            Err(_) => {
+                println!("Error: invalid frames\n");
+                app.print_help().unwrap();
+                println!();
+                return;
+            },
+        }
+    }
+
+    if let Some(until) = matches.value_of("until") {
+        match runner::Until::parse(until) {
+            Some(until) => runner.until = Some(until),
+            None => {
+                println!("Error: invalid until\n");
+                app.print_help().unwrap();
+                println!();
+                return;
+            },
+        }
+    }
+    let until = runner.until;
 
-    let machine = machine::Machine::open(freq, matches.value_of("file").unwrap()).unwrap();
-    let mut frontend = frontends::GlutinWindow::new();
+    let scale = matches.value_of("scale").unwrap().parse::<usize>();
+    let palette = capture::Style::parse_palette(matches.value_of("palette").unwrap());
+    runner.style = match (scale, palette) {
+        (Ok(scale), Some(palette)) if (1..=16).contains(&scale) => {
+            capture::Style { scale, palette }
+        },
+        _ => {
+            println!("Error: invalid scale or palette\n");
+            app.print_help().unwrap();
+            println!();
+            return;
+        },
+    };
+    if let Some(path) = matches.value_of("gif") {
+        match capture::Gif::create(path, runner.style) {
+            Ok(gif) => runner.gif = Some(gif),
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            },
+        }
+    }
+    let style = runner.style;
+
+    let volume = matches.value_of("volume").unwrap().parse::<u8>();
+    let frequency = matches.value_of("tone").unwrap().parse::<f64>();
+    let (volume, frequency) = match (volume, frequency) {
+        (Ok(volume), Ok(frequency)) if volume <= 100 && frequency > 0.0 => (volume, frequency),
+        _ => {
+            println!("Error: invalid volume or tone\n");
+            app.print_help().unwrap();
+            println!();
+            return;
+        },
+    };
+    let waveform = sound::Waveform::parse(matches.value_of("waveform").unwrap()).unwrap();
+    let synth = sound::Synth::new(waveform, frequency, f64::from(volume) / 100.0);
+
+    let keymap_path = matches
+        .value_of("keymap")
+        .map(Into::into)
+        .or_else(|| keymap::Keymap::default_path().filter(|p| p.exists()));
+    let keymap = match keymap_path {
+        Some(path) => match keymap::Keymap::load(path, file) {
+            Ok(keymap) => keymap,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            },
+        },
+        None => keymap::Keymap::default(),
+    };
+
+    let (machine, outcome) = match frontend_name {
+        "terminal" => {
+            let mut terminal = match frontends::Terminal::new(synth, keymap) {
+                Ok(terminal) => terminal,
+                Err(e) => {
+                    println!("Error: could not set up the terminal: {}", e);
+                    return;
+                },
+            };
+            runner.run(&mut terminal, machine)
+        },
+        "headless" => {
+            let mut headless = frontends::Headless::new();
+            if let Some(input) = matches.value_of("input") {
+                let script = match std::fs::read_to_string(input) {
+                    Ok(script) => script,
+                    Err(e) => {
+                        println!("Error: could not read {}: {}", input, e);
+                        return;
+                    },
+                };
+                match frontends::Headless::parse_input(&script) {
+                    Ok(input) => headless.input = input,
+                    Err(line) => {
+                        println!("Error: {}:{}: invalid input", input, line);
+                        return;
+                    },
+                }
+            }
+            if matches.is_present("wav") {
+                headless.synth = Some(synth);
+            }
+
+            let (mut machine, outcome) = runner.run(&mut headless, machine);
+
+            let screen = machine.screen();
+            let screen = frontends::headless::dump(&screen.buffer(), screen.hires());
+            match matches.value_of("dump") {
+                Some(path) => {
+                    if let Err(e) = std::fs::write(path, screen) {
+                        println!("Error: could not write {}: {}", path, e);
+                    }
+                },
+                None => print!("{}", screen),
+            }
+            if let Some(path) = matches.value_of("png") {
+                let screen = machine.screen();
+                let result = capture::screenshot(path, &screen.buffer(), screen.hires(), style);
+                if let Err(e) = result {
+                    println!("Error: {}", e);
+                }
+            }
+            if let Some(path) = matches.value_of("wav") {
+                if let Err(e) = sound::write_wav(path, sound::SAMPLE_RATE, &headless.samples) {
+                    println!("Error: could not write {}: {}", path, e);
+                }
+            }
 
-    frontend.run(freq, machine);
+            (machine, outcome)
+        },
+        _ => runner.run(&mut frontends::GlutinWindow::new(synth, keymap), machine),
+    };
+
+    // A played movie starts from its own flags, the ones of the ROM are left as they were
+    if !matches.is_present("play") && machine.rpl_flags() != [0; 16] {
+        if let Err(e) = std::fs::write(&rpl_path, machine.rpl_flags()) {
+            println!("Error: could not write {}: {}", rpl_path, e);
+        }
+    }
+    // Flushes the trace file before exiting
+    drop(machine);
+
+    // Lets scripts tell whether the ROM got where it was expected to
+    if let runner::Outcome::Crashed(e) = outcome {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    if until.is_some() && !matches!(outcome, runner::Outcome::Reached) {
+        eprintln!("Error: condition not reached");
+        std::process::exit(2);
+    }
 }
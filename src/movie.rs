@@ -0,0 +1,752 @@
+//! Input movies, replaying a run exactly
+//!
+//! A movie is either read-only, played over the frontend's input, or read-write, recording the
+//! frontend's input over the rest of it. States saved during a movie keep the input up to them in
+//! a movie file next to the state, loading one rewinds a read-only movie to it or replaces the
+//! input of a read-write one with it, which counts as a rerecord.
+//!
+//! Movie files start with `CH8M`, a version byte and a header, all little-endian:
+//!
+//! | Bytes | Content                                                     |
+//! |-------|-------------------------------------------------------------|
+//! | 8     | FNV-1a hash of the ROM                                      |
+//! | 8     | seed of the random number generator                         |
+//! | 1     | timed random numbers                                        |
+//! | 7     | quirks: shift, index increment, jump, logic, clipping,      |
+//! |       | low-res scrolling, collision rows                           |
+//! | 4     | memory size                                                 |
+//! | 4     | instructions per frame at the start                         |
+//! | 1     | VIP timing                                                  |
+//! | 16    | RPL flags                                                   |
+//! | 4     | rerecord count                                              |
+//!
+//! followed by the runs until the end of the file, each starting with a tag byte:
+//!
+//! | Tag | Bytes | Content                                                   |
+//! |-----|-------|-----------------------------------------------------------|
+//! | 0   | 2 + 4 | bit mask of the keys pressed for `count` instructions     |
+//! | 1   |       | end of a frame, the timers ticked                         |
+//! | 2   | 8     | hash of the state saved after the last frame              |
+//! | 3   |       | end of a lag frame, where the keypad wasn't read          |
+//!
+//! Version 2 movies, with 5 bytes of quirks and no low-res scrolling, and version 3 movies, with 6
+//! bytes of quirks and no collision rows, are still played.
+
+use crate::machine::Machine;
+use crate::quirks::{IndexIncrement, Quirks};
+use std::convert::TryFrom;
+use std::path::Path;
+
+pub const MAGIC: &[u8; 4] = b"CH8M";
+pub const VERSION: u8 = 4;
+
+/// Frames between two state hashes
+const CHECK_INTERVAL: u64 = 60;
+
+/// 64-bit FNV-1a
+pub fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+/// Everything the run depends on besides the input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub timed_random: bool,
+    pub quirks: Quirks,
+    pub ipf: u32,
+    pub vip_timing: bool,
+    pub rpl: [u8; 16],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Run {
+    Steps { keys: u16, count: u32 },
+    Frame { lag: bool },
+    Check(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Read-write
+    Record,
+    /// Read-only
+    Play,
+}
+
+/// Shown by the frontend during a movie
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Counter {
+    pub frame: u64,
+    pub lag_frames: u64,
+    pub mode: Mode,
+    pub rerecords: u32,
+}
+
+impl std::fmt::Display for Counter {
+    /// `frame 600, 12 lag, read-only, 3 rerecords`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "frame {}, {} lag, {}, {} rerecords",
+            self.frame,
+            self.lag_frames,
+            match self.mode {
+                Mode::Record => "read-write",
+                Mode::Play => "read-only",
+            },
+            self.rerecords
+        )
+    }
+}
+
+pub struct Movie {
+    pub header: Header,
+    pub mode: Mode,
+    /// States loaded in read-write mode
+    pub rerecords: u32,
+    runs: Vec<Run>,
+    /// Next run to play, and the instructions of it already played
+    position: usize,
+    played: u32,
+    /// Frames recorded or played, and the lag frames among them
+    frames: u64,
+    lag_frames: u64,
+    /// A hash didn't match, reported once
+    desynced: bool,
+    /// Changed since it was loaded
+    edited: bool,
+}
+
+impl Movie {
+    pub fn record(header: Header) -> Self {
+        Self {
+            header,
+            mode: Mode::Record,
+            rerecords: 0,
+            runs: Vec::new(),
+            position: 0,
+            played: 0,
+            frames: 0,
+            lag_frames: 0,
+            desynced: false,
+            edited: false,
+        }
+    }
+
+    /// Reads a movie to play
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut data = data;
+        let mut take = |len: usize| {
+            if data.len() < len {
+                return Err("movie is truncated".to_string());
+            }
+            let (v, rest) = data.split_at(len);
+            data = rest;
+            Ok(v)
+        };
+        let u32 = |v: &[u8]| u32::from_le_bytes(<[u8; 4]>::try_from(v).unwrap());
+        let u64 = |v: &[u8]| u64::from_le_bytes(<[u8; 8]>::try_from(v).unwrap());
+
+        if take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err("not a movie".to_string());
+        }
+        let version = take(1)?[0];
+        if !(2..=VERSION).contains(&version) {
+            return Err(format!("unsupported movie version {}", version));
+        }
+
+        let rom_hash = u64(take(8)?);
+        let seed = u64(take(8)?);
+        let timed_random = take(1)?[0] != 0;
+        // Version 2 has no scrolling quirk, version 3 no collision rows
+        let flags = take(match version {
+            2 => 5,
+            3 => 6,
+            _ => 7,
+        })?;
+        let quirks = Quirks {
+            shift_uses_vy: flags[0] != 0,
+            index_increment: match flags[1] {
+                0 => IndexIncrement::Unchanged,
+                1 => IndexIncrement::X,
+                2 => IndexIncrement::XPlusOne,
+                _ => return Err("movie contains an invalid value".to_string()),
+            },
+            jump_uses_vx: flags[2] != 0,
+            logic_resets_vf: flags[3] != 0,
+            clip_sprites: flags[4] != 0,
+            scroll_lowres_pixels: flags.get(5).is_some_and(|f| *f != 0),
+            collision_rows: flags.get(6).is_some_and(|f| *f != 0),
+            memory_size: u32(take(4)?) as usize,
+        };
+        let ipf = u32(take(4)?);
+        if ![0x1000, 0x10000].contains(&quirks.memory_size) || ipf == 0 {
+            return Err("movie contains an invalid value".to_string());
+        }
+        let vip_timing = take(1)?[0] != 0;
+        let mut rpl = [0; 16];
+        rpl.copy_from_slice(take(16)?);
+        let rerecords = u32(take(4)?);
+
+        let mut runs = Vec::new();
+        while let Ok(tag) = take(1) {
+            runs.push(match tag[0] {
+                0 => {
+                    let keys = take(2)?;
+                    Run::Steps {
+                        keys: u16::from_le_bytes([keys[0], keys[1]]),
+                        count: u32(take(4)?),
+                    }
+                },
+                1 => Run::Frame { lag: false },
+                2 => Run::Check(u64(take(8)?)),
+                3 => Run::Frame { lag: true },
+                _ => return Err("movie contains an invalid value".to_string()),
+            });
+        }
+
+        let header = Header {
+            rom_hash,
+            seed,
+            timed_random,
+            quirks,
+            ipf,
+            vip_timing,
+            rpl,
+        };
+        Ok(Self {
+            runs,
+            mode: Mode::Play,
+            rerecords,
+            ..Self::record(header)
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes(&self.runs))
+    }
+
+    /// Saves the input up to now, to load along with a state
+    pub fn save_branch(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes(&self.recorded()))
+    }
+
+    fn to_bytes(&self, runs: &[Run]) -> Vec<u8> {
+        let header = &self.header;
+        let quirks = &header.quirks;
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+
+        data.extend_from_slice(&header.rom_hash.to_le_bytes());
+        data.extend_from_slice(&header.seed.to_le_bytes());
+        data.push(header.timed_random as _);
+        data.push(quirks.shift_uses_vy as _);
+        data.push(match quirks.index_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::X => 1,
+            IndexIncrement::XPlusOne => 2,
+        });
+        data.push(quirks.jump_uses_vx as _);
+        data.push(quirks.logic_resets_vf as _);
+        data.push(quirks.clip_sprites as _);
+        data.push(quirks.scroll_lowres_pixels as _);
+        data.push(quirks.collision_rows as _);
+        data.extend_from_slice(&(quirks.memory_size as u32).to_le_bytes());
+        data.extend_from_slice(&header.ipf.to_le_bytes());
+        data.push(header.vip_timing as _);
+        data.extend_from_slice(&header.rpl);
+        data.extend_from_slice(&self.rerecords.to_le_bytes());
+
+        for run in runs.iter() {
+            match *run {
+                Run::Steps { keys, count } => {
+                    data.push(0);
+                    data.extend_from_slice(&keys.to_le_bytes());
+                    data.extend_from_slice(&count.to_le_bytes());
+                },
+                Run::Frame { lag: false } => data.push(1),
+                Run::Frame { lag: true } => data.push(3),
+                Run::Check(hash) => {
+                    data.push(2);
+                    data.extend_from_slice(&hash.to_le_bytes());
+                },
+            }
+        }
+
+        data
+    }
+
+    /// Runs up to the current position, the last one cut at the instruction being played
+    fn recorded(&self) -> Vec<Run> {
+        if self.mode == Mode::Record {
+            return self.runs.clone();
+        }
+
+        let mut runs = self.runs[..self.position].to_vec();
+        if let Some(Run::Steps { keys, .. }) = self.runs.get(self.position) {
+            if self.played > 0 {
+                runs.push(Run::Steps {
+                    keys: *keys,
+                    count: self.played,
+                });
+            }
+        }
+        runs
+    }
+
+    /// Keys of the next instruction to play, `None` at the end of a frame
+    pub fn next_keys(&self) -> Option<[bool; 16]> {
+        match self.runs.get(self.position)? {
+            Run::Steps { keys, .. } => {
+                let mut pressed = [false; 16];
+                for (i, key) in pressed.iter_mut().enumerate() {
+                    *key = keys & 1 << i != 0;
+                }
+                Some(pressed)
+            },
+            _ => None,
+        }
+    }
+
+    /// Records an instruction run with `keys`, or moves past a played one
+    pub fn step(&mut self, keys: [bool; 16]) {
+        match self.mode {
+            Mode::Record => {
+                self.edited = true;
+                self.record_step(keys);
+            },
+            // Kept in case of a switch to read-write, see `record_step`
+            Mode::Play if self.finished() => {
+                self.record_step(keys);
+                self.position = self.runs.len();
+            },
+            Mode::Play => {
+                if let Some(Run::Steps { count, .. }) = self.runs.get(self.position) {
+                    self.played += 1;
+                    if self.played == *count {
+                        self.position += 1;
+                        self.played = 0;
+                    }
+                }
+            },
+        }
+    }
+
+    /// Records the end of a frame, or moves past a played one checking the state of `machine`,
+    /// returns an error the first time it doesn't match the recording
+    pub fn frame(&mut self, machine: &Machine, lag: bool) -> Result<(), String> {
+        let finished = self.finished();
+        self.frames += 1;
+        self.lag_frames += lag as u64;
+        let check = self.frames.is_multiple_of(CHECK_INTERVAL);
+
+        match self.mode {
+            Mode::Record => self.record_frame(machine, lag, check),
+            Mode::Play if finished => {
+                self.record_frame(machine, lag, check);
+                self.position = self.runs.len();
+            },
+            Mode::Play => {
+                if let Some(Run::Frame { .. }) = self.runs.get(self.position) {
+                    self.position += 1;
+                }
+                if let Some(Run::Check(expected)) = self.runs.get(self.position) {
+                    self.position += 1;
+                    if *expected != hash(&machine.save_state()) && !self.desynced {
+                        self.desynced = true;
+                        return Err(format!("movie desynced at frame {}", self.frames));
+                    }
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Appends the input of an instruction. Once a read-only movie is over the frontend's input
+    /// is appended too, without saving it unless the movie is switched to read-write, so the run
+    /// in between isn't lost
+    fn record_step(&mut self, keys: [bool; 16]) {
+        let mask = (0..16)
+            .filter(|i| keys[*i])
+            .fold(0u16, |mask, i| mask | 1 << i);
+        match self.runs.last_mut() {
+            Some(Run::Steps { keys, count }) if *keys == mask && *count < u32::MAX => *count += 1,
+            _ => self.runs.push(Run::Steps {
+                keys: mask,
+                count: 1,
+            }),
+        }
+    }
+
+    fn record_frame(&mut self, machine: &Machine, lag: bool, check: bool) {
+        self.runs.push(Run::Frame { lag });
+        if check {
+            self.runs.push(Run::Check(hash(&machine.save_state())));
+        }
+    }
+
+    /// Frames recorded or played so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn counter(&self) -> Counter {
+        Counter {
+            frame: self.frames,
+            lag_frames: self.lag_frames,
+            mode: self.mode,
+            rerecords: self.rerecords,
+        }
+    }
+
+    /// Has to be saved
+    pub fn edited(&self) -> bool {
+        self.edited
+    }
+
+    /// Switches between read-only and read-write, the input after the current position is
+    /// dropped when switching to read-write
+    pub fn toggle_mode(&mut self) {
+        match self.mode {
+            Mode::Play => {
+                self.runs = self.recorded();
+                self.mode = Mode::Record;
+                self.edited = true;
+            },
+            Mode::Record => {
+                self.mode = Mode::Play;
+                self.position = self.runs.len();
+                self.played = 0;
+            },
+        }
+        self.count_frames();
+    }
+
+    /// Whether `branch` can load the movie saved along with a state
+    pub fn check_branch(&self, branch: &Movie) -> Result<(), String> {
+        if branch.header != self.header {
+            return Err("the state was saved during another movie".to_string());
+        }
+        if self.mode == Mode::Play && self.seek_position(&branch.runs).is_none() {
+            return Err("the state is not part of the read-only movie".to_string());
+        }
+        Ok(())
+    }
+
+    /// Goes back to where a state was saved, checked by `check_branch`
+    pub fn branch(&mut self, branch: Movie) {
+        match self.mode {
+            Mode::Play => {
+                let (position, played) = self.seek_position(&branch.runs).unwrap();
+                self.position = position;
+                self.played = played;
+            },
+            Mode::Record => {
+                self.runs = branch.runs;
+                self.rerecords += 1;
+                self.edited = true;
+            },
+        }
+        self.count_frames();
+    }
+
+    /// Position of the end of `runs` in the movie, if they're the start of it
+    fn seek_position(&self, runs: &[Run]) -> Option<(usize, u32)> {
+        let (last, rest) = match runs.split_last() {
+            Some(split) => split,
+            None => return Some((0, 0)),
+        };
+        if !self.runs.starts_with(rest) {
+            return None;
+        }
+
+        match (*last, *self.runs.get(rest.len())?) {
+            (last, run) if last == run => Some((rest.len() + 1, 0)),
+            // Saved in the middle of a run
+            (
+                Run::Steps { keys, count },
+                Run::Steps {
+                    keys: k,
+                    count: total,
+                },
+            ) if keys == k && count < total => Some((rest.len(), count)),
+            _ => None,
+        }
+    }
+
+    /// Counts the frames before the current position
+    fn count_frames(&mut self) {
+        let end = match self.mode {
+            Mode::Record => self.runs.len(),
+            Mode::Play => self.position,
+        };
+        self.frames = 0;
+        self.lag_frames = 0;
+        for run in self.runs[..end].iter() {
+            if let Run::Frame { lag } = run {
+                self.frames += 1;
+                self.lag_frames += *lag as u64;
+            }
+        }
+    }
+
+    /// Everything was played
+    pub fn finished(&self) -> bool {
+        self.mode == Mode::Play && self.position >= self.runs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie() -> Movie {
+        let header = Header {
+            rom_hash: hash(b"rom"),
+            seed: 42,
+            timed_random: true,
+            quirks: Quirks::preset("schip").unwrap(),
+            ipf: 30,
+            vip_timing: false,
+            rpl: [3; 16],
+        };
+        Movie {
+            rerecords: 5,
+            runs: vec![
+                Run::Steps {
+                    keys: 0x8001,
+                    count: 30,
+                },
+                Run::Frame { lag: false },
+                Run::Check(0x0123_4567_89AB_CDEF),
+                Run::Frame { lag: true },
+                Run::Steps {
+                    keys: 0,
+                    count: 100_000,
+                },
+            ],
+            ..Movie::record(header)
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let movie = movie();
+        let parsed = Movie::parse(&movie.to_bytes(&movie.runs)).unwrap();
+        assert_eq!(parsed.header, movie.header);
+        assert_eq!(parsed.rerecords, movie.rerecords);
+        assert_eq!(parsed.runs, movie.runs);
+        assert_eq!(parsed.mode, Mode::Play);
+    }
+
+    #[test]
+    fn truncated_is_rejected() {
+        let movie = movie();
+        let data = movie.to_bytes(&movie.runs);
+        assert_eq!(
+            Movie::parse(&data[..3]).err().as_deref(),
+            Some("not a movie")
+        );
+        for len in [20, data.len() - 1, data.len() - 5].iter() {
+            assert_eq!(
+                Movie::parse(&data[..*len]).err().as_deref(),
+                Some("movie is truncated"),
+                "{} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_tag_is_rejected() {
+        let movie = movie();
+        let mut data = movie.to_bytes(&movie.runs);
+        data.push(4);
+        assert_eq!(
+            Movie::parse(&data).err().as_deref(),
+            Some("movie contains an invalid value")
+        );
+    }
+
+    #[test]
+    fn older_versions_are_played() {
+        for version in [2, 3].iter() {
+            let mut movie = movie();
+            movie.header.quirks.scroll_lowres_pixels &= *version > 2;
+            movie.header.quirks.collision_rows = false;
+            let mut data = movie.to_bytes(&movie.runs);
+            // Without the quirk bytes added since, 5 in version 2
+            data[4] = *version;
+            let quirks = 4 + 1 + 8 + 8 + 1;
+            data.drain(quirks + usize::from(*version) + 3..quirks + 7);
+            let parsed = Movie::parse(&data).unwrap();
+            assert_eq!(parsed.header, movie.header, "version {}", version);
+            assert_eq!(parsed.runs, movie.runs);
+        }
+    }
+
+    /// Keys of the instructions of frame `n`
+    fn keys(n: u64) -> [[bool; 16]; 2] {
+        let mut keys = [[false; 16]; 2];
+        keys[0][n as usize % 16] = true;
+        keys[1][(n as usize + 1) % 16] = true;
+        keys
+    }
+
+    /// Records or plays a frame, with the movie's keys while playing
+    fn run_frame(movie: &mut Movie, n: u64) {
+        let playing = movie.mode == Mode::Play && !movie.finished();
+        for keys in keys(n).iter() {
+            match playing {
+                true => movie.step(movie.next_keys().unwrap()),
+                false => movie.step(*keys),
+            }
+        }
+        if playing {
+            assert_eq!(movie.next_keys(), None);
+        }
+        movie
+            .frame(&Machine::new(Quirks::default(), &[]), n.is_multiple_of(3))
+            .unwrap();
+    }
+
+    /// Read-only movie of `frames` frames
+    fn played(frames: u64) -> Movie {
+        let mut movie = movie();
+        movie.runs.clear();
+        for n in 0..frames {
+            run_frame(&mut movie, n);
+        }
+        Movie::parse(&movie.to_bytes(&movie.runs)).unwrap()
+    }
+
+    /// What `save_branch` writes after `frames` frames and `steps` instructions of the next one
+    fn branch_at(frames: u64, steps: usize) -> Movie {
+        let mut movie = played(frames);
+        movie.mode = Mode::Record;
+        for keys in keys(frames)[..steps].iter() {
+            movie.step(*keys);
+        }
+        Movie::parse(&movie.to_bytes(&movie.recorded())).unwrap()
+    }
+
+    #[test]
+    fn read_only_load_seeks() {
+        let mut movie = played(10);
+        for n in 0..8 {
+            run_frame(&mut movie, n);
+        }
+
+        let branch = branch_at(4, 1);
+        movie.check_branch(&branch).unwrap();
+        movie.branch(branch);
+        assert_eq!(movie.counter().frame, 4);
+        assert_eq!(movie.counter().lag_frames, 2);
+        assert_eq!(movie.next_keys(), Some(keys(4)[1]));
+        assert_eq!(movie.runs.len(), played(10).runs.len());
+        assert!(!movie.edited());
+
+        // And plays on from there
+        movie.step(keys(4)[1]);
+        movie
+            .frame(&Machine::new(Quirks::default(), &[]), false)
+            .unwrap();
+        for n in 5..10 {
+            run_frame(&mut movie, n);
+        }
+        assert!(movie.finished());
+        assert_eq!(movie.frames(), 10);
+    }
+
+    #[test]
+    fn read_write_load_truncates() {
+        let mut movie = played(10);
+        movie.toggle_mode();
+        assert_eq!(movie.mode, Mode::Record);
+
+        let branch = branch_at(4, 0);
+        movie.check_branch(&branch).unwrap();
+        movie.branch(branch);
+        assert_eq!(movie.runs, played(4).runs);
+        assert_eq!(movie.rerecords, 6);
+        assert_eq!(movie.frames(), 4);
+        assert!(movie.edited());
+    }
+
+    #[test]
+    fn read_only_load_needs_a_prefix() {
+        let movie = played(10);
+        let mut branch = branch_at(4, 1);
+        branch.runs[0] = Run::Steps {
+            keys: 0xFFFF,
+            count: 1,
+        };
+        assert_eq!(
+            movie.check_branch(&branch),
+            Err("the state is not part of the read-only movie".to_string())
+        );
+
+        let mut branch = branch_at(4, 1);
+        branch.header.seed += 1;
+        assert_eq!(
+            movie.check_branch(&branch),
+            Err("the state was saved during another movie".to_string())
+        );
+
+        // Anything goes in read-write mode
+        let mut movie = movie;
+        movie.toggle_mode();
+        let mut branch = branch_at(4, 1);
+        branch.runs[0] = Run::Frame { lag: true };
+        assert_eq!(movie.check_branch(&branch), Ok(()));
+    }
+
+    #[test]
+    fn read_write_drops_the_rest() {
+        let mut movie = played(10);
+        for n in 0..3 {
+            run_frame(&mut movie, n);
+        }
+        movie.step(keys(3)[0]);
+
+        movie.toggle_mode();
+        assert_eq!(movie.mode, Mode::Record);
+        assert_eq!(movie.runs, branch_at(3, 1).runs);
+        assert_eq!(movie.frames(), 3);
+        assert!(movie.edited());
+    }
+
+    #[test]
+    fn frames_after_the_end_are_kept() {
+        let mut movie = movie();
+        movie.runs.clear();
+        for n in 0..3 {
+            run_frame(&mut movie, n);
+        }
+        movie.toggle_mode();
+        assert!(movie.finished());
+        // As if it had just been saved
+        movie.edited = false;
+
+        // Played over from the frontend, then kept when switching to read-write
+        for n in 3..5 {
+            run_frame(&mut movie, n);
+        }
+        assert_eq!(movie.frames(), 5);
+        assert!(!movie.edited());
+        movie.toggle_mode();
+        assert_eq!(movie.runs, played(5).runs);
+        assert_eq!(movie.frames(), 5);
+    }
+}
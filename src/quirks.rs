@@ -0,0 +1,105 @@
+/// How `StoreRegisters`/`RestoreRegisters` change I
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndexIncrement {
+    /// I is left untouched
+    Unchanged,
+    /// I = I + x
+    X,
+    /// I = I + x + 1
+    XPlusOne,
+}
+
+/// Interpretations of the opcodes that behave differently between CHIP-8 implementations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    /// `Shr`/`Shl` shift Vy and store the result in Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    pub index_increment: IndexIncrement,
+    /// `JumpPlus` jumps to Vx + a (x being the highest nibble of a) instead of V0 + a
+    pub jump_uses_vx: bool,
+    /// `Or`/`And`/`Xor` reset VF to 0
+    pub logic_resets_vf: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    /// Scrolling in low-res mode moves whole low-res pixels instead of half of them like
+    /// SUPER-CHIP 1.1
+    pub scroll_lowres_pixels: bool,
+    /// In high-res mode, `Draw` sets VF to the number of sprite rows that collided or were clipped
+    /// off the bottom of the screen instead of 1, like SUPER-CHIP 1.1
+    pub collision_rows: bool,
+    /// Amount of addressable memory in bytes
+    pub memory_size: usize,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Self = Self {
+        shift_uses_vy: true,
+        index_increment: IndexIncrement::XPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        clip_sprites: true,
+        scroll_lowres_pixels: false,
+        collision_rows: false,
+        memory_size: 0x1000,
+    };
+
+    pub const CHIP_48: Self = Self {
+        shift_uses_vy: false,
+        index_increment: IndexIncrement::X,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        scroll_lowres_pixels: false,
+        collision_rows: false,
+        memory_size: 0x1000,
+    };
+
+    pub const SUPER_CHIP: Self = Self {
+        shift_uses_vy: false,
+        index_increment: IndexIncrement::Unchanged,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        scroll_lowres_pixels: false,
+        collision_rows: true,
+        memory_size: 0x1000,
+    };
+
+    pub const XO_CHIP: Self = Self {
+        shift_uses_vy: true,
+        index_increment: IndexIncrement::XPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        clip_sprites: false,
+        scroll_lowres_pixels: true,
+        collision_rows: false,
+        memory_size: 0x10000,
+    };
+
+    pub const PRESETS: [&'static str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "vip" => Some(Self::COSMAC_VIP),
+            "chip48" => Some(Self::CHIP_48),
+            "schip" => Some(Self::SUPER_CHIP),
+            "xochip" => Some(Self::XO_CHIP),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            scroll_lowres_pixels: false,
+            collision_rows: false,
+            memory_size: 0x1000,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+/// Deterministic source of the `LoadRandom` bytes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Random {
+    /// splitmix64
+    SplitMix(u64),
+    /// A 16-bit seed advanced by every instruction and mixed into itself by every `LoadRandom`,
+    /// so the bytes depend on when the ROM asks for them
+    Timed(u16),
+}
+
+impl Random {
+    pub fn new(seed: u64, timed: bool) -> Self {
+        if timed {
+            Random::Timed(seed as u16)
+        } else {
+            Random::SplitMix(seed)
+        }
+    }
+
+    /// Called for every executed instruction
+    pub fn step(&mut self) {
+        if let Random::Timed(seed) = self {
+            *seed = seed.wrapping_add(1);
+        }
+    }
+
+    pub fn next(&mut self) -> u8 {
+        match self {
+            Random::SplitMix(state) => {
+                *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                let mut z = *state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                ((z ^ (z >> 31)) >> 56) as u8
+            },
+            Random::Timed(seed) => {
+                let [low, high] = seed.to_le_bytes();
+                let high = high.rotate_left(1).wrapping_add(low) ^ 0xA5;
+                *seed = u16::from_le_bytes([low, high]);
+                high
+            },
+        }
+    }
+
+    /// Kind and state, 9 bytes
+    pub fn to_bytes(self) -> [u8; 9] {
+        let (kind, state) = match self {
+            Random::SplitMix(state) => (0, state),
+            Random::Timed(seed) => (1, u64::from(seed)),
+        };
+        let mut bytes = [kind; 9];
+        bytes[1..].copy_from_slice(&state.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut state = [0; 8];
+        state.copy_from_slice(bytes.get(1..9)?);
+        let state = u64::from_le_bytes(state);
+
+        match bytes[0] {
+            0 => Some(Random::SplitMix(state)),
+            1 if state <= 0xFFFF => Some(Random::Timed(state as u16)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splitmix_sequence() {
+        // The high bytes of the reference splitmix64 outputs for seed 0
+        let mut random = Random::new(0, false);
+        let bytes: Vec<_> = (0..5).map(|_| random.next()).collect();
+        assert_eq!(bytes, [0xE2, 0x6E, 0x06, 0xF8, 0x1B]);
+    }
+
+    #[test]
+    fn timed_sequence() {
+        let mut random = Random::new(0x1234, true);
+        let bytes: Vec<_> = (0..5)
+            .map(|_| {
+                random.step();
+                random.next()
+            })
+            .collect();
+        assert_eq!(bytes, [0xFC, 0x8A, 0xE9, 0xAE, 0x33]);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        for random in [Random::new(u64::MAX, false), Random::new(0xBEEF, true)].iter() {
+            assert_eq!(Random::from_bytes(&random.to_bytes()), Some(*random));
+        }
+
+        let mut bytes = Random::Timed(0).to_bytes();
+        bytes[3] = 1;
+        assert_eq!(Random::from_bytes(&bytes), None);
+        bytes[0] = 2;
+        assert_eq!(Random::from_bytes(&bytes), None);
+        assert_eq!(Random::from_bytes(&bytes[..8]), None);
+    }
+}
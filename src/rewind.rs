@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+/// Ring buffer of machine states, every state is stored as a difference to the one after it
+pub struct Rewind {
+    capacity: usize,
+    deltas: VecDeque<Vec<u8>>,
+    last: Option<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            deltas: VecDeque::with_capacity(capacity),
+            last: None,
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(last) = &self.last {
+            if last.len() == state.len() {
+                self.deltas.push_back(encode_delta(last, &state));
+                if self.deltas.len() > self.capacity {
+                    self.deltas.pop_front();
+                }
+            } else {
+                self.deltas.clear();
+            }
+        }
+
+        self.last = Some(state);
+    }
+
+    /// Returns the state pushed before the last one and forgets the last one
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let last = self.last.as_mut()?;
+        apply_delta(last, &delta);
+
+        Some(last.clone())
+    }
+
+    /// Forgets every state
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.last = None;
+    }
+}
+
+// A delta is a list of (zero count, literal count, literals) runs of `old XOR new`,
+// counts being u16 LE
+
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut pos = 0;
+
+    while pos < old.len() {
+        let zeros = (pos..old.len())
+            .take(0xFFFF)
+            .take_while(|i| old[*i] == new[*i])
+            .count();
+        pos += zeros;
+
+        let literals = (pos..old.len())
+            .take(0xFFFF)
+            .take_while(|i| old[*i] != new[*i])
+            .count();
+
+        delta.extend_from_slice(&(zeros as u16).to_le_bytes());
+        delta.extend_from_slice(&(literals as u16).to_le_bytes());
+        delta.extend((pos..pos + literals).map(|i| old[i] ^ new[i]));
+        pos += literals;
+    }
+
+    delta
+}
+
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut delta = delta;
+
+    while delta.len() >= 4 {
+        let zeros = u16::from_le_bytes([delta[0], delta[1]]) as usize;
+        let literals = u16::from_le_bytes([delta[2], delta[3]]) as usize;
+        pos += zeros;
+
+        for (s, d) in state[pos..pos + literals]
+            .iter_mut()
+            .zip(&delta[4..4 + literals])
+        {
+            *s ^= d;
+        }
+        pos += literals;
+        delta = &delta[4 + literals..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(old: &[u8], new: &[u8]) {
+        let mut state = new.to_vec();
+        apply_delta(&mut state, &encode_delta(old, new));
+        assert_eq!(state, old);
+    }
+
+    #[test]
+    fn delta_round_trips() {
+        round_trip(&[], &[]);
+        round_trip(&[1, 2, 3], &[1, 2, 3]);
+        round_trip(&[1, 2, 3, 4, 5], &[1, 9, 9, 4, 0]);
+        round_trip(&[0; 100], &[0xFF; 100]);
+
+        // Runs longer than a u16 count
+        let old = vec![0; 0x20000];
+        let mut new = old.clone();
+        new[0x10001..0x1FFFF].iter_mut().for_each(|b| *b = 7);
+        round_trip(&old, &new);
+    }
+
+    #[test]
+    fn rewind_restores_states_in_reverse() {
+        let mut rewind = Rewind::new(10);
+        for state in [[1, 2, 3], [1, 5, 3], [4, 5, 6]].iter() {
+            rewind.push(state.to_vec());
+        }
+        assert_eq!(rewind.pop(), Some(vec![1, 5, 3]));
+        assert_eq!(rewind.pop(), Some(vec![1, 2, 3]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn rewind_stops_at_length_changes() {
+        let mut rewind = Rewind::new(10);
+        rewind.push(vec![1, 2, 3]);
+        rewind.push(vec![1, 2, 3, 4]);
+        rewind.push(vec![1, 2, 0, 4]);
+        assert_eq!(rewind.pop(), Some(vec![1, 2, 3, 4]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn rewind_keeps_capacity_states() {
+        let mut rewind = Rewind::new(2);
+        for i in 0..5 {
+            rewind.push(vec![i]);
+        }
+        assert_eq!(rewind.pop(), Some(vec![3]));
+        assert_eq!(rewind.pop(), Some(vec![2]));
+        assert_eq!(rewind.pop(), None);
+    }
+}
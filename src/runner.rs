@@ -0,0 +1,603 @@
+use crate::capture::{self, Gif, Style};
+use crate::debugger::{parse_addr, Debugger, Stop};
+use crate::frontends::{Event, Frontend};
+use crate::machine::{Machine, MachineError};
+use crate::movie::{Counter, Mode, Movie};
+use crate::rewind::Rewind;
+use crate::screen::{HEIGHT, WIDTH};
+use crate::sound::Tone;
+use std::convert::TryFrom;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+};
+use std::time::Duration;
+
+/// Stop condition checked after every step
+#[derive(Clone, Copy, Debug)]
+pub enum Until {
+    Pc(usize),
+    Register(usize, u8),
+    Memory(usize, u8),
+}
+
+impl Until {
+    /// Parses `pc=0x200`, `v3=0x10` or `[0x300]=1`
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(2, '=');
+        let lhs = parts.next()?.trim().to_lowercase();
+        let rhs = parse_addr(parts.next()?.trim())?;
+
+        if lhs == "pc" {
+            Some(Until::Pc(rhs))
+        } else if let Some(x) = lhs.strip_prefix('v') {
+            let x = usize::from_str_radix(x, 16).ok().filter(|x| *x < 16)?;
+            Some(Until::Register(x, u8::try_from(rhs).ok()?))
+        } else if let Some(addr) = lhs.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+            Some(Until::Memory(parse_addr(addr)?, u8::try_from(rhs).ok()?))
+        } else {
+            None
+        }
+    }
+
+    fn reached(self, machine: &Machine) -> bool {
+        match self {
+            Until::Pc(pc) => machine.pc() == pc,
+            Until::Register(x, value) => machine.registers()[x] == value,
+            Until::Memory(addr, value) => machine.memory().get(addr) == Some(&value),
+        }
+    }
+}
+
+pub enum Outcome {
+    /// Quit by the frontend or after the requested number of frames
+    Quit,
+    Halted,
+    Reached,
+    Crashed(MachineError),
+}
+
+/// Sent by the machine thread at the start of every 60hz frame
+struct Frame {
+    number: u64,
+    screen: Option<([[u8; WIDTH]; HEIGHT], bool)>,
+    tone: Option<Tone>,
+    counter: Option<Counter>,
+    /// Shown by the frontend, for problems of the machine thread
+    status: Option<String>,
+}
+
+enum Command {
+    SaveState(String),
+    LoadState(String),
+    ToggleMovieMode,
+}
+
+/// What to run while paused
+enum Advance {
+    /// Up to the start of the next frame
+    Frame,
+    Instruction,
+}
+
+/// Sent back by the frontend for every frame
+#[derive(Default)]
+struct Input {
+    keys: [bool; 16],
+    rewinding: bool,
+    commands: Vec<Command>,
+    /// While paused, the same frame is sent again until `advance` is set
+    paused: bool,
+    advance: Option<Advance>,
+    ipf: u32,
+}
+
+/// Runs the machine on its own thread while the frontend handles output and input on the
+/// calling one, exchanging a frame and its input every 60hz tick so runs are deterministic
+pub struct Runner {
+    /// Instructions per 60hz frame
+    pub ipf: u32,
+    /// Charge COSMAC VIP machine cycles instead of running `ipf` instructions per frame
+    pub vip_timing: bool,
+    /// Quick-save slots are stored next to `state_path` as `<state_path>.state<slot>`
+    pub state_path: String,
+    pub rewind: Rewind,
+    pub debugger: Option<Debugger>,
+    /// Stop after this many 60hz frames
+    pub frames: Option<u64>,
+    pub until: Option<Until>,
+    /// Run as fast as possible instead of at 60 frames per second
+    pub fast: bool,
+    /// Input recorded or played instead of the frontend's, rewinding is off meanwhile
+    pub movie: Option<Movie>,
+    /// The movie is written there once the run is over if it changed
+    pub movie_path: Option<String>,
+    /// Screenshots are saved next to `state_path` as `<state_path>.shot<n>.png`
+    pub style: Style,
+    /// Records every screen change
+    pub gif: Option<Gif>,
+}
+
+impl Runner {
+    pub fn new(ipf: u32, state_path: &str, rewind: Rewind) -> Self {
+        Self {
+            ipf,
+            vip_timing: false,
+            state_path: state_path.to_string(),
+            rewind,
+            debugger: None,
+            frames: None,
+            until: None,
+            fast: false,
+            movie: None,
+            movie_path: None,
+            style: Style::default(),
+            gif: None,
+        }
+    }
+
+    pub fn run(mut self, frontend: &mut dyn Frontend, machine: Machine) -> (Machine, Outcome) {
+        let running = Arc::new(AtomicBool::new(true));
+        let (frames, frames_recv) = mpsc::channel();
+        let (inputs, inputs_recv) = mpsc::channel();
+        let state_path = self.state_path.clone();
+        let ipf = self.ipf;
+        let vip_timing = self.vip_timing;
+        let style = self.style;
+        let mut gif = self.gif.take();
+
+        let machine_thread = {
+            let running = running.clone();
+            std::thread::spawn(move || self.machine_loop(machine, running, frames, inputs_recv))
+        };
+
+        let mut input = Input {
+            ipf,
+            ..Input::default()
+        };
+        let mut number = 0;
+        let mut slot = 0;
+        let mut tone = None;
+        let mut counter = None;
+        let mut screen = None;
+        let mut quit = false;
+
+        frontend.set_speed((!vip_timing).then_some(input.ipf), input.paused);
+
+        while !quit {
+            let frame = match frames_recv.recv_timeout(Duration::from_millis(16)) {
+                Ok(frame) => Some(frame),
+                // Paused by the debugger, keep the frontend responsive
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+
+            if let Some(frame) = &frame {
+                number = frame.number;
+                if let Some((buffer, hires)) = &frame.screen {
+                    frontend.present(buffer, *hires);
+                    screen = Some((*buffer, *hires));
+
+                    if let Some(recording) = &mut gif {
+                        if let Err(e) = recording.frame(frame.number, buffer) {
+                            eprintln!("Error: {}", e);
+                            gif = None;
+                        }
+                    }
+                }
+                if frame.tone != tone {
+                    tone = frame.tone;
+                    match tone {
+                        Some(tone) => frontend.play_tone(tone),
+                        None => frontend.stop_tone(),
+                    }
+                }
+                if frame.counter != counter {
+                    counter = frame.counter;
+                    frontend.set_counter(counter.as_ref());
+                }
+                if let Some(status) = &frame.status {
+                    frontend.set_status(status);
+                }
+            }
+
+            for event in frontend.poll_input(number, &mut input.keys) {
+                let slot_path = format!("{}.state{}", state_path, slot);
+                match event {
+                    Event::SaveState => input.commands.push(Command::SaveState(slot_path)),
+                    Event::LoadState => input.commands.push(Command::LoadState(slot_path)),
+                    Event::PreviousSlot | Event::NextSlot => {
+                        slot = match event {
+                            Event::PreviousSlot => (slot + 9) % 10,
+                            _ => (slot + 1) % 10,
+                        };
+                        frontend.set_status(&format!("slot {}", slot));
+                    },
+                    Event::Rewind(rewinding) => input.rewinding = rewinding,
+                    Event::ToggleReadOnly => input.commands.push(Command::ToggleMovieMode),
+                    Event::Screenshot => {
+                        if let Some((buffer, hires)) = &screen {
+                            let path = (0..)
+                                .map(|n| format!("{}.shot{}.png", state_path, n))
+                                .find(|path| !std::path::Path::new(path).exists())
+                                .unwrap();
+                            match capture::screenshot(&path, buffer, *hires, style) {
+                                Ok(()) => frontend.set_status(&format!("saved {}", path)),
+                                Err(e) => frontend.set_status(&e),
+                            }
+                        }
+                    },
+                    Event::Pause => {
+                        input.paused = !input.paused;
+                        frontend.set_speed((!vip_timing).then_some(input.ipf), input.paused);
+                    },
+                    Event::AdvanceFrame if input.paused => input.advance = Some(Advance::Frame),
+                    Event::StepInstruction if input.paused => {
+                        input.advance = Some(Advance::Instruction)
+                    },
+                    Event::AdvanceFrame | Event::StepInstruction => {},
+                    Event::Faster | Event::Slower if !vip_timing => {
+                        // By about 10%
+                        let change = (input.ipf / 10).max(1);
+                        input.ipf = match event {
+                            Event::Faster => input.ipf.saturating_add(change),
+                            _ => input.ipf.saturating_sub(change).max(1),
+                        };
+                        frontend.set_speed((!vip_timing).then_some(input.ipf), input.paused);
+                    },
+                    Event::Faster | Event::Slower => {},
+                    Event::Quit => quit = true,
+                }
+            }
+
+            if quit {
+                running.store(false, Ordering::SeqCst);
+            }
+            if frame.is_some() {
+                let next = Input {
+                    keys: input.keys,
+                    rewinding: input.rewinding,
+                    commands: std::mem::take(&mut input.commands),
+                    paused: input.paused,
+                    advance: input.advance.take(),
+                    ipf: input.ipf,
+                };
+                inputs.send(next).ok();
+            }
+        }
+
+        running.store(false, Ordering::SeqCst);
+        drop(inputs);
+        let (mut machine, outcome) = machine_thread.join().unwrap();
+
+        // Along with the last changes, shown from the next frame on
+        if let Some(mut recording) = gif {
+            let result = recording
+                .frame(number + 1, &machine.screen().buffer())
+                .and_then(|_| recording.finish(number + 2));
+            if let Err(e) = result {
+                eprintln!("Error: {}", e);
+            }
+        }
+
+        if tone.is_some() {
+            frontend.stop_tone();
+        }
+
+        // Keep showing the crashed machine until the frontend quits
+        if let Outcome::Crashed(e) = &outcome {
+            let screen = machine.screen();
+            frontend.present(&screen.buffer(), screen.hires());
+            frontend.crashed(e);
+            let mut keys = [false; 16];
+            while !quit {
+                quit = frontend
+                    .poll_input(number, &mut keys)
+                    .iter()
+                    .any(|e| matches!(e, Event::Quit));
+                std::thread::sleep(Duration::from_millis(16));
+            }
+        }
+
+        (machine, outcome)
+    }
+
+    fn machine_loop(
+        mut self,
+        mut machine: Machine,
+        running: Arc<AtomicBool>,
+        frames: mpsc::Sender<Frame>,
+        inputs: mpsc::Receiver<Input>,
+    ) -> (Machine, Outcome) {
+        let mut loop_helper = spin_sleep::LoopHelper::builder().build_with_target_rate(60.0);
+        let mut frame = 0;
+        let mut status = None;
+
+        let outcome = 'frames: loop {
+            if !self.fast {
+                loop_helper.loop_start();
+            }
+
+            if !running.load(Ordering::SeqCst) {
+                break Outcome::Quit;
+            }
+            if machine.halted() {
+                break Outcome::Halted;
+            }
+            if self.frames.is_some_and(|frames| frame >= frames) {
+                break Outcome::Quit;
+            }
+
+            let counter = self.movie.as_ref().map(Movie::counter);
+            let exchanged = exchange(
+                &mut machine,
+                frame,
+                false,
+                counter,
+                status.take(),
+                &frames,
+                &inputs,
+            );
+            let mut input = match exchanged {
+                Some(input) => input,
+                None => break Outcome::Quit,
+            };
+            frame += 1;
+            self.handle_input(&mut machine, &mut input);
+
+            // Record or restore one state per frame
+            input.rewinding &= self.movie.is_none();
+            if input.rewinding {
+                if let Some(state) = self.rewind.pop() {
+                    // Going further back would only fail again
+                    if let Err(e) = machine.load_state(&state) {
+                        status = Some(format!("could not rewind: {}", e));
+                        self.rewind.clear();
+                    }
+                }
+            } else {
+                self.rewind.push(machine.save_state());
+            }
+
+            // Keep sending the current frame until asked to run something
+            let mut executed = 0;
+            while input.paused {
+                match input.advance.take() {
+                    Some(Advance::Frame) => break,
+                    Some(Advance::Instruction) => {
+                        match self.step(&mut machine, input.keys, &running, executed) {
+                            Ok(true) => executed += 1,
+                            Ok(false) => break,
+                            Err(outcome) => break 'frames outcome,
+                        }
+                    },
+                    None => {
+                        std::thread::sleep(Duration::from_millis(16));
+                        let counter = self.movie.as_ref().map(Movie::counter);
+                        let paused = exchange(
+                            &mut machine,
+                            frame - 1,
+                            true,
+                            counter,
+                            status.take(),
+                            &frames,
+                            &inputs,
+                        );
+                        input = match paused {
+                            Some(input) => input,
+                            None => break 'frames Outcome::Quit,
+                        };
+                        self.handle_input(&mut machine, &mut input);
+                    },
+                }
+            }
+
+            if !input.rewinding {
+                if self.debugger.is_none() && self.until.is_none() && self.movie.is_none() {
+                    let result = if self.vip_timing {
+                        machine.run_vip_frame(input.keys)
+                    } else {
+                        machine.run_frame(self.ipf.saturating_sub(executed), input.keys)
+                    };
+                    if let Err(e) = result {
+                        break Outcome::Crashed(e);
+                    }
+                } else {
+                    // Breakpoints, conditions and movies work one instruction at a time
+                    loop {
+                        match self.step(&mut machine, input.keys, &running, executed) {
+                            Ok(true) => executed += 1,
+                            Ok(false) => break,
+                            Err(outcome) => break 'frames outcome,
+                        }
+                    }
+                    machine.tick_timers();
+                    self.end_movie_frame(&mut machine);
+                }
+            }
+
+            if !self.fast {
+                loop_helper.loop_sleep();
+            }
+        };
+
+        if let (Some(movie), Some(path)) = (&self.movie, &self.movie_path) {
+            if movie.edited() {
+                if let Err(e) = movie.save(path) {
+                    eprintln!("Error: could not save {}: {}", path, e);
+                }
+            }
+        }
+
+        (machine, outcome)
+    }
+
+    /// Records or checks the end of the frame, the frontend's input is used once a read-only
+    /// movie is over
+    fn end_movie_frame(&mut self, machine: &mut Machine) {
+        let lag = !machine.take_input_polled();
+        if let Some(movie) = &mut self.movie {
+            let finished = movie.finished();
+            if let Err(e) = movie.frame(machine, lag) {
+                eprintln!("Error: {}", e);
+            }
+            if !finished && movie.finished() {
+                eprintln!("Movie finished after {} frames", movie.frames());
+            }
+        }
+    }
+
+    /// Runs the next instruction of a frame that already ran `executed`, waiting while the
+    /// debugger is paused, returns false if the frame is over
+    fn step(
+        &mut self,
+        machine: &mut Machine,
+        keys: [bool; 16],
+        running: &AtomicBool,
+        executed: u32,
+    ) -> Result<bool, Outcome> {
+        let done = if self.vip_timing {
+            machine.halted() || machine.vip_frame_done()
+        } else {
+            executed >= self.ipf
+        };
+        // A played movie decides where frames end
+        let playing = |m: &&Movie| m.mode == Mode::Play && !m.finished();
+        let keys = match self.movie.as_ref().filter(playing) {
+            Some(movie) => match movie.next_keys() {
+                Some(keys) => keys,
+                None => return Ok(false),
+            },
+            None if done => return Ok(false),
+            None => keys,
+        };
+
+        if let Some(debugger) = &mut self.debugger {
+            while !debugger.before_step(machine) {
+                if !running.load(Ordering::SeqCst) {
+                    return Err(Outcome::Quit);
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        let result = if self.vip_timing {
+            machine.vip_step(keys)
+        } else {
+            machine.step(keys)
+        };
+        if let Err(e) = result {
+            if let Some(debugger) = &mut self.debugger {
+                debugger.notify_stop(Stop::Crashed);
+            }
+            return Err(Outcome::Crashed(e));
+        }
+
+        if let Some(movie) = &mut self.movie {
+            movie.step(keys);
+        }
+
+        // Instead of pausing, a halted machine is over
+        if let Some(debugger) = &mut self.debugger {
+            if machine.halted() {
+                debugger.notify_stop(Stop::Halted);
+            } else {
+                debugger.after_step(machine);
+            }
+        }
+
+        if self.until.is_some_and(|until| until.reached(machine)) {
+            return Err(Outcome::Reached);
+        }
+        if machine.halted() {
+            return Err(Outcome::Halted);
+        }
+
+        Ok(true)
+    }
+
+    /// Runs the commands and applies the speed of `input`
+    fn handle_input(&mut self, machine: &mut Machine, input: &mut Input) {
+        for command in input.commands.drain(..) {
+            match command {
+                Command::SaveState(path) => {
+                    if let Err(e) = std::fs::write(&path, machine.save_state()) {
+                        eprintln!("Error: could not save {}: {}", path, e);
+                    }
+                    // The input up to the state, to branch off the movie from there
+                    let movie_path = format!("{}.movie", path);
+                    let result = match &self.movie {
+                        Some(movie) => movie.save_branch(&movie_path),
+                        None => std::fs::remove_file(&movie_path).or(Ok(())),
+                    };
+                    if let Err(e) = result {
+                        eprintln!("Error: could not save {}: {}", movie_path, e);
+                    }
+                },
+                Command::LoadState(path) => {
+                    let result = std::fs::read(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|state| self.load_state(machine, &path, &state));
+                    if let Err(e) = result {
+                        eprintln!("Error: could not load {}: {}", path, e);
+                    }
+                },
+                Command::ToggleMovieMode => {
+                    if let Some(movie) = &mut self.movie {
+                        movie.toggle_mode();
+                    }
+                },
+            }
+        }
+
+        self.ipf = input.ipf;
+    }
+
+    /// Loads the state saved at `path`, along with the input up to it during a movie
+    fn load_state(
+        &mut self,
+        machine: &mut Machine,
+        path: &str,
+        state: &[u8],
+    ) -> Result<(), String> {
+        match &mut self.movie {
+            Some(movie) => {
+                let branch = Movie::load(format!("{}.movie", path))?;
+                movie.check_branch(&branch)?;
+                machine.load_state(state).map_err(|e| e.to_string())?;
+                movie.branch(branch);
+            },
+            None => machine.load_state(state).map_err(|e| e.to_string())?,
+        }
+        Ok(())
+    }
+}
+
+/// Sends the frame `number` with the current screen, tone and status, then waits for its input
+fn exchange(
+    machine: &mut Machine,
+    number: u64,
+    paused: bool,
+    counter: Option<Counter>,
+    status: Option<String>,
+    frames: &mpsc::Sender<Frame>,
+    inputs: &mpsc::Receiver<Input>,
+) -> Option<Input> {
+    let screen = machine.screen();
+    let frame = Frame {
+        number,
+        screen: if screen.needs_redraw() {
+            screen.redrawn();
+            Some((screen.buffer(), screen.hires()))
+        } else {
+            None
+        },
+        // Timers don't run while paused
+        tone: if paused { None } else { machine.tone() },
+        counter,
+        status,
+    };
+
+    frames.send(frame).ok()?;
+    inputs.recv().ok()
+}
@@ -1,3 +1,5 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
 pub const SPRITES: [u8; 5 * 16] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -17,52 +19,212 @@ pub const SPRITES: [u8; 5 * 16] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+pub const BIG_SPRITES: [u8; 10 * 16] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 64;
+
+/// Every pixel of the buffer holds one bit per bitplane: bit 0 is plane 1, bit 1 is plane 2
 pub struct Screen {
-    buffer: [[bool; 64]; 32],
+    buffer: [[u8; WIDTH]; HEIGHT],
+    hires: bool,
+    planes: u8,
     redraw: bool,
 }
 
 impl Screen {
     pub fn new() -> Self {
         Self {
-            buffer: [[false; 64]; 32],
+            buffer: [[0; WIDTH]; HEIGHT],
+            hires: false,
+            planes: 1,
             redraw: true,
         }
     }
 
+    /// Clears the selected planes
     pub fn clear(&mut self) {
-        self.buffer = [[false; 64]; 32];
+        for row in self.buffer.iter_mut() {
+            for pix in row.iter_mut() {
+                *pix &= !self.planes;
+            }
+        }
         self.redraw = true;
     }
 
-    pub fn draw(&mut self, x_start: u8, y_start: u8, sprite: &[u8]) -> bool {
-        let mut collision = false;
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.buffer = [[0; WIDTH]; HEIGHT];
+        self.redraw = true;
+    }
 
-        for y in 0..sprite.len() {
-            for x in 0..8 {
-                let x_pos = (x_start as usize + x) % 64;
-                let y_pos = (y_start as usize + y) % 32;
-                let pix = &mut self.buffer[y_pos][x_pos];
+    /// Selects the planes affected by `clear`, `draw` and scrolling
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
+    }
+    pub fn plane_count(&self) -> usize {
+        self.planes.count_ones() as _
+    }
 
-                let sprite_pix = sprite[y as usize] & (1 << (7 - x)) != 0;
+    /// Size of one logical pixel in buffer pixels: 2 in low-res mode, 1 in high-res mode
+    fn scale(&self) -> usize {
+        if self.hires {
+            1
+        } else {
+            2
+        }
+    }
 
-                if !*pix && sprite_pix {
-                    *pix = true;
-                    self.redraw = true;
-                } else if *pix && sprite_pix {
-                    *pix = false;
-                    collision = true;
+    /// Buffer pixels to scroll for `n` pixels
+    fn scroll_amount(&self, n: usize, whole_pixels: bool) -> usize {
+        if whole_pixels {
+            n * self.scale()
+        } else {
+            n
+        }
+    }
+
+    /// Draws a sprite `width` pixels wide (8 or 16) from `sprite`, using `width / 8` bytes per row.
+    /// `sprite` holds the data for every selected plane one after another. Returns the number of
+    /// sprite rows that collided with a lit pixel, counted for each plane
+    pub fn draw(
+        &mut self,
+        x_start: u8,
+        y_start: u8,
+        sprite: &[u8],
+        width: usize,
+        clip: bool,
+    ) -> usize {
+        let mut collisions = 0;
+
+        let scale = self.scale();
+        let (logical_width, logical_height) = (WIDTH / scale, HEIGHT / scale);
+
+        // The starting position always wraps, only the parts of the sprite
+        // that cross the edges are clipped
+        let x_start = x_start as usize % logical_width;
+        let y_start = y_start as usize % logical_height;
+
+        let plane_count = self.plane_count();
+        if plane_count == 0 {
+            return 0;
+        }
+        let mut plane_sprites = sprite.chunks(sprite.len() / plane_count);
+
+        let planes = self.planes;
+        for plane in [1, 2].iter().filter(|p| planes & *p != 0) {
+            let plane_sprite = plane_sprites.next().unwrap_or(&[]);
+
+            for (y, row) in plane_sprite.chunks(width / 8).enumerate() {
+                let mut collision = false;
+                for x in 0..width {
+                    if clip && (x_start + x >= logical_width || y_start + y >= logical_height) {
+                        continue;
+                    }
+
+                    let sprite_pix = row[x / 8] & (1 << (7 - x % 8)) != 0;
+                    if !sprite_pix {
+                        continue;
+                    }
+
+                    let x_pos = (x_start + x) % logical_width * scale;
+                    let y_pos = (y_start + y) % logical_height * scale;
+
+                    if self.buffer[y_pos][x_pos] & plane != 0 {
+                        collision = true;
+                    }
+                    for row in &mut self.buffer[y_pos..y_pos + scale] {
+                        for p in &mut row[x_pos..x_pos + scale] {
+                            *p ^= plane;
+                        }
+                    }
                     self.redraw = true;
                 }
+                collisions += collision as usize;
             }
         }
 
-        collision
+        collisions
     }
 
-    pub fn buffer(&self) -> [[bool; 64]; 32] {
+    /// Scrolls `n` buffer pixels down, or `n` logical pixels with `whole_pixels`
+    pub fn scroll_down(&mut self, n: usize, whole_pixels: bool) {
+        let n = self.scroll_amount(n, whole_pixels).min(HEIGHT);
+        for y in (0..HEIGHT).rev() {
+            for x in 0..WIDTH {
+                let src = if y >= n { self.buffer[y - n][x] } else { 0 };
+                self.buffer[y][x] = self.buffer[y][x] & !self.planes | src & self.planes;
+            }
+        }
+        self.redraw = true;
+    }
+
+    /// Scrolls 4 buffer pixels right, or 4 logical pixels with `whole_pixels`
+    pub fn scroll_right(&mut self, whole_pixels: bool) {
+        let n = self.scroll_amount(4, whole_pixels);
+        for row in self.buffer.iter_mut() {
+            for x in (0..WIDTH).rev() {
+                let src = if x >= n { row[x - n] } else { 0 };
+                row[x] = row[x] & !self.planes | src & self.planes;
+            }
+        }
+        self.redraw = true;
+    }
+
+    /// Scrolls 4 buffer pixels left, or 4 logical pixels with `whole_pixels`
+    pub fn scroll_left(&mut self, whole_pixels: bool) {
+        let n = self.scroll_amount(4, whole_pixels);
+        for row in self.buffer.iter_mut() {
+            for x in 0..WIDTH {
+                let src = if x + n < WIDTH { row[x + n] } else { 0 };
+                row[x] = row[x] & !self.planes | src & self.planes;
+            }
+        }
+        self.redraw = true;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.hires);
+        state.u8(self.planes);
+        for row in self.buffer.iter() {
+            state.bytes(row);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.hires = state.bool()?;
+        self.planes = state.u8()? & 0b11;
+        for row in self.buffer.iter_mut() {
+            row.copy_from_slice(state.bytes(WIDTH)?);
+        }
+        self.redraw = true;
+
+        Ok(())
+    }
+
+    pub fn buffer(&self) -> [[u8; WIDTH]; HEIGHT] {
         self.buffer
     }
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
     pub fn needs_redraw(&self) -> bool {
         self.redraw
     }
@@ -70,3 +232,117 @@ impl Screen {
         self.redraw = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Buffer pixels set in plane 1, as (x, y)
+    fn lit(screen: &Screen) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for (y, row) in screen.buffer.iter().enumerate() {
+            for (x, pix) in row.iter().enumerate() {
+                if pix & 1 != 0 {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn lowres_pixels_are_doubled() {
+        let mut screen = Screen::new();
+        assert_eq!(screen.draw(1, 2, &[0x80], 8, false), 0);
+        assert_eq!(lit(&screen), [(2, 4), (3, 4), (2, 5), (3, 5)]);
+
+        // Drawing again erases it and reports the collision
+        assert_eq!(screen.draw(1, 2, &[0x80], 8, false), 1);
+        assert_eq!(lit(&screen), []);
+    }
+
+    #[test]
+    fn hires_sprites_are_16_wide() {
+        let mut screen = Screen::new();
+        screen.set_hires(true);
+        let mut sprite = [0; 32];
+        sprite[0] = 0x80;
+        sprite[31] = 0x01;
+        screen.draw(10, 20, &sprite, 16, false);
+        assert_eq!(lit(&screen), [(10, 20), (25, 35)]);
+    }
+
+    #[test]
+    fn clipping_and_wrapping() {
+        let mut screen = Screen::new();
+        screen.draw(63, 31, &[0xC0, 0xC0], 8, true);
+        assert_eq!(lit(&screen), [(126, 62), (127, 62), (126, 63), (127, 63)]);
+
+        let mut screen = Screen::new();
+        screen.draw(63, 31, &[0xC0, 0xC0], 8, false);
+        assert_eq!(lit(&screen).len(), 16);
+        assert!(lit(&screen).contains(&(0, 0)));
+
+        // The starting position wraps either way
+        let mut screen = Screen::new();
+        screen.draw(64 + 1, 32, &[0x80], 8, true);
+        assert_eq!(lit(&screen), [(2, 0), (3, 0), (2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn lowres_scrolling() {
+        // Half a low-res pixel like SUPER-CHIP 1.1
+        let mut screen = Screen::new();
+        screen.draw(0, 0, &[0x80], 8, false);
+        screen.scroll_down(1, false);
+        assert_eq!(lit(&screen), [(0, 1), (1, 1), (0, 2), (1, 2)]);
+        screen.scroll_right(false);
+        assert_eq!(lit(&screen), [(4, 1), (5, 1), (4, 2), (5, 2)]);
+
+        // Whole low-res pixels like XO-CHIP
+        let mut screen = Screen::new();
+        screen.draw(0, 0, &[0x80], 8, false);
+        screen.scroll_down(1, true);
+        assert_eq!(lit(&screen), [(0, 2), (1, 2), (0, 3), (1, 3)]);
+        screen.scroll_right(true);
+        assert_eq!(lit(&screen), [(8, 2), (9, 2), (8, 3), (9, 3)]);
+        screen.scroll_left(true);
+        screen.scroll_left(true);
+        assert_eq!(lit(&screen), []);
+    }
+
+    #[test]
+    fn hires_scrolling() {
+        for whole_pixels in [false, true].iter() {
+            let mut screen = Screen::new();
+            screen.set_hires(true);
+            screen.draw(10, 10, &[0x80], 8, false);
+            screen.scroll_down(3, *whole_pixels);
+            screen.scroll_left(*whole_pixels);
+            assert_eq!(lit(&screen), [(6, 13)]);
+        }
+    }
+
+    #[test]
+    fn planes() {
+        let mut screen = Screen::new();
+        screen.set_hires(true);
+        screen.set_planes(3);
+        screen.draw(0, 0, &[0x80, 0xC0], 8, false);
+        assert_eq!(screen.buffer[0][..2], [3, 2]);
+
+        // Only the selected planes are scrolled and cleared
+        screen.set_planes(2);
+        screen.scroll_down(1, false);
+        assert_eq!(screen.buffer[0][..2], [1, 0]);
+        assert_eq!(screen.buffer[1][..2], [2, 2]);
+        screen.clear();
+        assert_eq!(screen.buffer[0][..2], [1, 0]);
+        assert_eq!(screen.buffer[1][..2], [0, 0]);
+
+        // No plane draws nothing
+        screen.set_planes(0);
+        assert_eq!(screen.draw(0, 0, &[], 8, false), 0);
+        assert_eq!(screen.buffer[0][..2], [1, 0]);
+    }
+}
@@ -0,0 +1,337 @@
+//! Beeps and XO-CHIP audio patterns
+//!
+//! Sound is only played when built with the `sound` feature, `cargo build --features sound`.
+//! It isn't on by default because cpal needs the ALSA development files on Linux, and without them
+//! the whole build would fail. Without it the speaker is silent and the sound hotkeys say so, WAV
+//! files are written either way.
+
+use std::io::Write;
+#[cfg(feature = "sound")]
+use std::sync::{Arc, Mutex};
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    Sawtooth,
+}
+
+impl Waveform {
+    pub const NAMES: [&'static str; 4] = ["square", "triangle", "sine", "sawtooth"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sine" => Some(Waveform::Sine),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            _ => None,
+        }
+    }
+
+    /// One period of the wave, `phase` in 0..1
+    fn sample(self, phase: f64) -> f64 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sine => (phase * std::f64::consts::TAU).sin(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+/// What the machine plays while the sound timer is active
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tone {
+    Beep,
+    /// XO-CHIP 1-bit audio pattern and pitch
+    Pattern([u8; 16], u8),
+}
+
+/// Generates samples for the current tone
+#[derive(Clone, Debug)]
+pub struct Synth {
+    pub waveform: Waveform,
+    /// Of `Tone::Beep`, in Hz
+    pub frequency: f64,
+    /// 0 to 1
+    pub volume: f64,
+    pub muted: bool,
+    pub tone: Option<Tone>,
+    phase: f64,
+}
+
+impl Synth {
+    pub fn new(waveform: Waveform, frequency: f64, volume: f64) -> Self {
+        Self {
+            waveform,
+            frequency,
+            volume,
+            muted: false,
+            tone: None,
+            phase: 0.0,
+        }
+    }
+
+    /// Fills `out` with mono samples at `sample_rate`
+    pub fn fill(&mut self, sample_rate: u32, out: &mut [f32]) {
+        let volume = if self.muted { 0.0 } else { self.volume };
+
+        for sample in out.iter_mut() {
+            let value = match self.tone {
+                None => 0.0,
+                Some(Tone::Beep) => {
+                    self.phase = (self.phase + self.frequency / f64::from(sample_rate)).fract();
+                    self.waveform.sample(self.phase)
+                },
+                Some(Tone::Pattern(pattern, pitch)) => {
+                    // 4000 * 2^((pitch - 64) / 48) bits per second, looping over 128 bits
+                    let rate = 4000.0 * 2f64.powf((f64::from(pitch) - 64.0) / 48.0);
+                    self.phase = (self.phase + rate / f64::from(sample_rate)) % 128.0;
+                    let bit = self.phase as usize;
+                    if pattern[bit / 8] >> (7 - bit % 8) & 1 != 0 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                },
+            };
+            *sample = (value * volume) as f32;
+        }
+    }
+}
+
+/// Writes 16-bit mono PCM
+pub fn write_wav(path: &str, sample_rate: u32, samples: &[f32]) -> std::io::Result<()> {
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let data_len = samples.len() as u32 * 2;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&1u16.to_le_bytes())?; // channels
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+    out.write_all(&2u16.to_le_bytes())?; // bytes per frame
+    out.write_all(&16u16.to_le_bytes())?; // bits per sample
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        out.write_all(&((sample.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes())?;
+    }
+
+    out.flush()
+}
+
+/// Shown instead of the volume when the speaker is silent
+const NO_BACKEND: &str = "no sound, built without the sound feature";
+
+/// Status of the mute hotkey
+pub fn mute_status(muted: bool) -> &'static str {
+    match (cfg!(feature = "sound"), muted) {
+        (false, _) => NO_BACKEND,
+        (true, true) => "muted",
+        (true, false) => "unmuted",
+    }
+}
+
+/// Status of the volume hotkeys
+pub fn volume_status(volume: f64) -> String {
+    match cfg!(feature = "sound") {
+        false => NO_BACKEND.to_string(),
+        true => format!("volume {:.0}%", volume * 100.0),
+    }
+}
+
+/// Plays a `Synth` on the default output device, silent unless built with the `sound` feature
+pub struct Speaker {
+    #[cfg(feature = "sound")]
+    synth: Arc<Mutex<Synth>>,
+    #[cfg(feature = "sound")]
+    _stream: cpal::Stream,
+    #[cfg(not(feature = "sound"))]
+    synth: Synth,
+}
+
+impl Speaker {
+    #[cfg(feature = "sound")]
+    pub fn open(synth: Synth) -> Option<Self> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        let synth = Arc::new(Mutex::new(synth));
+
+        let stream = (|| {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or_else(|| "no output device".to_string())?;
+            let config = device.default_output_config().map_err(|e| e.to_string())?;
+            if config.sample_format() != cpal::SampleFormat::F32 {
+                return Err(format!(
+                    "unsupported sample format {}",
+                    config.sample_format()
+                ));
+            }
+
+            let channels = config.channels() as usize;
+            let sample_rate = config.sample_rate();
+            let callback_synth = synth.clone();
+            let mut mono = Vec::new();
+            let stream = device
+                .build_output_stream(
+                    config.into(),
+                    move |data: &mut [f32], _| {
+                        mono.resize(data.len() / channels, 0.0);
+                        callback_synth.lock().unwrap().fill(sample_rate, &mut mono);
+                        for (frame, sample) in data.chunks_mut(channels).zip(mono.iter()) {
+                            frame.iter_mut().for_each(|s| *s = *sample);
+                        }
+                    },
+                    |e| eprintln!("Error: audio: {}", e),
+                    None,
+                )
+                .map_err(|e| e.to_string())?;
+            stream.play().map_err(|e| e.to_string())?;
+
+            Ok(stream)
+        })();
+
+        match stream {
+            Ok(stream) => Some(Self {
+                synth,
+                _stream: stream,
+            }),
+            Err(e) => {
+                eprintln!("Warning: no sound: {}", e);
+                None
+            },
+        }
+    }
+
+    #[cfg(not(feature = "sound"))]
+    pub fn open(synth: Synth) -> Option<Self> {
+        Some(Self { synth })
+    }
+
+    fn with_synth<T>(&mut self, f: impl FnOnce(&mut Synth) -> T) -> T {
+        #[cfg(feature = "sound")]
+        return f(&mut self.synth.lock().unwrap());
+        #[cfg(not(feature = "sound"))]
+        return f(&mut self.synth);
+    }
+
+    pub fn set_tone(&mut self, tone: Option<Tone>) {
+        self.with_synth(|s| s.tone = tone);
+    }
+
+    /// Returns whether it is muted now
+    pub fn toggle_mute(&mut self) -> bool {
+        self.with_synth(|s| {
+            s.muted = !s.muted;
+            s.muted
+        })
+    }
+
+    /// Returns the new volume
+    pub fn change_volume(&mut self, delta: f64) -> f64 {
+        self.with_synth(|s| {
+            s.volume = (s.volume + delta).clamp(0.0, 1.0);
+            s.volume
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(synth: &mut Synth, len: usize) -> Vec<f32> {
+        let mut out = vec![1.0; len];
+        synth.fill(SAMPLE_RATE, &mut out);
+        out
+    }
+
+    #[test]
+    fn square_wave() {
+        // 100 samples per period
+        let mut synth = Synth::new(Waveform::Square, 441.0, 0.5);
+        synth.tone = Some(Tone::Beep);
+        let out = samples(&mut synth, 1000);
+
+        assert!(out.iter().all(|s| *s == 0.5 || *s == -0.5));
+        let high = out.iter().filter(|s| **s > 0.0).count();
+        assert!((495..=505).contains(&high), "{} high samples", high);
+        let rises = out.windows(2).filter(|w| w[0] < 0.0 && w[1] > 0.0).count();
+        assert!((9..=10).contains(&rises), "{} periods", rises);
+    }
+
+    #[test]
+    fn silent_without_tone() {
+        // The sound timer is 0
+        let machine = crate::machine::Machine::new(Default::default(), &[]);
+        let mut synth = Synth::new(Waveform::Square, 441.0, 1.0);
+        synth.tone = machine.tone();
+        assert!(samples(&mut synth, 100).iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn mute() {
+        let mut synth = Synth::new(Waveform::Sine, 441.0, 1.0);
+        synth.tone = Some(Tone::Beep);
+        synth.muted = true;
+        assert!(samples(&mut synth, 100).iter().all(|s| *s == 0.0));
+        synth.muted = false;
+        assert!(samples(&mut synth, 100).iter().any(|s| *s != 0.0));
+    }
+
+    #[test]
+    fn pattern() {
+        // 4000 bits per second at pitch 64, one bit per sample at 4000 Hz
+        let mut pattern = [0; 16];
+        pattern[..8].copy_from_slice(&[0xFF; 8]);
+        let mut synth = Synth::new(Waveform::Square, 441.0, 1.0);
+        synth.tone = Some(Tone::Pattern(pattern, 64));
+        let mut out = [0.0; 128];
+        synth.fill(4000, &mut out);
+
+        // Starting from the second bit
+        assert!(out[..63].iter().all(|s| *s == 1.0));
+        assert!(out[63..127].iter().all(|s| *s == -1.0));
+        assert_eq!(out[127], 1.0);
+    }
+
+    #[test]
+    fn wav() {
+        let path = std::env::temp_dir().join(format!("chip8-{}.wav", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_wav(path, 8000, &[0.0, 1.0, -1.0, 2.0]).unwrap();
+        let data = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+        let u32 =
+            |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        assert_eq!(data.len(), 44 + 4 * 2);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32(4), 36 + 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!((u16(20), u16(22)), (1, 1));
+        assert_eq!((u32(24), u32(28)), (8000, 16000));
+        assert_eq!((u16(32), u16(34)), (2, 16));
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32(40), 8);
+
+        let samples: Vec<_> = (0..4).map(|i| u16(44 + i * 2) as i16).collect();
+        assert_eq!(samples, [0, 32767, -32767, 32767]);
+    }
+}
@@ -0,0 +1,115 @@
+//! Save states
+//!
+//! States start with `CH8S` and a version byte, followed by the machine and its screen as
+//! written by `Machine::save_state`, all little-endian. The version is raised whenever that
+//! layout changes, and states of older versions are not converted: they can't be loaded and have
+//! to be saved again.
+
+pub const MAGIC: &[u8; 4] = b"CH8S";
+pub const VERSION: u8 = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    MemorySizeMismatch { expected: usize, found: usize },
+    InvalidValue,
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use StateError::*;
+        match self {
+            BadMagic => write!(f, "not a save state"),
+            UnsupportedVersion(v) => write!(
+                f,
+                "save state version {} is not supported, only version {} can be loaded",
+                v, VERSION
+            ),
+            Truncated => write!(f, "save state is truncated"),
+            MemorySizeMismatch { expected, found } => write!(
+                f,
+                "save state has {} bytes of memory, machine has {}",
+                found, expected
+            ),
+            InvalidValue => write!(f, "save state contains an invalid value"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        Self { data }
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+    pub fn bool(&mut self, v: bool) {
+        self.data.push(v as _);
+    }
+    pub fn u32(&mut self, v: usize) {
+        self.data.extend_from_slice(&(v as u32).to_le_bytes());
+    }
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.data.extend_from_slice(v);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        let mut reader = Self { data };
+        if reader
+            .bytes(MAGIC.len())
+            .map_err(|_| StateError::BadMagic)?
+            != MAGIC
+        {
+            return Err(StateError::BadMagic);
+        }
+        match reader.u8()? {
+            VERSION => Ok(reader),
+            v => Err(StateError::UnsupportedVersion(v)),
+        }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::InvalidValue),
+        }
+    }
+    pub fn u32(&mut self) -> Result<usize, StateError> {
+        let mut v = [0; 4];
+        v.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(v) as _)
+    }
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (v, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(v)
+    }
+}
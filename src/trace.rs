@@ -0,0 +1,325 @@
+//! Execution tracing
+//!
+//! Binary trace files start with `CH8T` and a version byte, followed by one 26 byte record per
+//! instruction, all little-endian:
+//!
+//! | Bytes | Content                                  |
+//! |-------|------------------------------------------|
+//! | 2     | PC                                       |
+//! | 2     | opcode, as in memory                     |
+//! | 2     | bit mask of the registers it changed     |
+//! | 16    | V0-VF after the instruction              |
+//! | 2     | I after the instruction                  |
+//! | 1     | delay timer                              |
+//! | 1     | sound timer                              |
+
+use crate::debugger::parse_addr;
+use crate::instruction::Instruction;
+use crate::machine::MachineError;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+pub const MAGIC: &[u8; 4] = b"CH8T";
+pub const VERSION: u8 = 1;
+
+/// One executed instruction
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub pc: usize,
+    pub opcode: [u8; 2],
+    pub instruction: Instruction,
+    /// Registers before and after the instruction
+    pub before: [u8; 16],
+    pub after: [u8; 16],
+    pub i: usize,
+    pub delay: u8,
+    pub sound: u8,
+}
+
+impl std::fmt::Display for Entry {
+    /// `0x200: (6A02) LD   VA, 0x02  VA=00->02 I=0x000 DT=00 ST=00`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let text = format!(
+            "0x{:03X}: ({:02X}{:02X}) {}",
+            self.pc, self.opcode[0], self.opcode[1], self.instruction
+        );
+        write!(f, "{:<32}", text)?;
+
+        for (x, (before, after)) in self.before.iter().zip(self.after.iter()).enumerate() {
+            if before != after {
+                write!(f, "V{:X}={:02X}->{:02X} ", x, before, after)?;
+            }
+        }
+        write!(
+            f,
+            "I=0x{:03X} DT={:02X} ST={:02X}",
+            self.i, self.delay, self.sound
+        )
+    }
+}
+
+/// Called by `Machine::step` for every executed instruction
+pub trait Tracer: Send {
+    fn trace(&mut self, entry: &Entry);
+
+    /// Called once the machine crashed
+    fn crashed(&mut self, _error: &MachineError) {}
+}
+
+pub enum Sink {
+    /// One line of text per instruction
+    Stderr,
+    File(std::io::BufWriter<std::fs::File>),
+    /// Keeps the last `capacity` instructions, printed to stderr on crash
+    Ring {
+        capacity: usize,
+        entries: VecDeque<Entry>,
+    },
+}
+
+impl Sink {
+    /// Parses `stderr`, `file:<path>` or `ring:<count>`, creating the file
+    pub fn open(spec: &str) -> Result<Self, String> {
+        if spec == "stderr" {
+            Ok(Sink::Stderr)
+        } else if let Some(path) = spec.strip_prefix("file:") {
+            let mut file = std::fs::File::create(path)
+                .map(std::io::BufWriter::new)
+                .map_err(|e| format!("could not create {}: {}", path, e))?;
+            file.write_all(MAGIC)
+                .and_then(|_| file.write_all(&[VERSION]))
+                .map_err(|e| format!("could not write {}: {}", path, e))?;
+            Ok(Sink::File(file))
+        } else if let Some(count) = spec.strip_prefix("ring:") {
+            let capacity = count
+                .parse()
+                .ok()
+                .filter(|c| *c > 0)
+                .ok_or_else(|| format!("invalid ring size '{}'", count))?;
+            Ok(Sink::Ring {
+                capacity,
+                entries: VecDeque::with_capacity(capacity),
+            })
+        } else {
+            Err(format!("invalid trace sink '{}'", spec))
+        }
+    }
+}
+
+/// Opcode with wildcards, like `Dxyn` or `8xy4`
+#[derive(Clone, Copy, Debug)]
+pub struct Pattern {
+    mask: u16,
+    value: u16,
+}
+
+impl Pattern {
+    /// Four characters, hex digits match themselves and anything else matches any digit
+    pub fn parse(s: &str) -> Option<Self> {
+        if s.chars().count() != 4 {
+            return None;
+        }
+
+        let mut pattern = Self { mask: 0, value: 0 };
+        for c in s.chars() {
+            pattern.mask <<= 4;
+            pattern.value <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                pattern.mask |= 0xF;
+                pattern.value |= digit as u16;
+            }
+        }
+        Some(pattern)
+    }
+
+    pub fn matches(self, opcode: [u8; 2]) -> bool {
+        u16::from_be_bytes(opcode) & self.mask == self.value
+    }
+}
+
+/// Instructions to trace, everything if empty
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    pub ranges: Vec<RangeInclusive<usize>>,
+    pub opcodes: Vec<Pattern>,
+}
+
+impl Filter {
+    /// Adds the comma separated address ranges of `s`, like `0x200-0x2FF,0x300`
+    pub fn add_ranges(&mut self, s: &str) -> Result<(), String> {
+        for range in s.split(',') {
+            self.ranges.push(Self::parse_range(range)?);
+        }
+        Ok(())
+    }
+
+    /// Adds the comma separated opcode patterns of `s`, like `Dxyn,8xy4`
+    pub fn add_opcodes(&mut self, s: &str) -> Result<(), String> {
+        for pattern in s.split(',') {
+            match Pattern::parse(pattern.trim()) {
+                Some(pattern) => self.opcodes.push(pattern),
+                None => return Err(format!("invalid trace opcode '{}'", pattern)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses `0x200-0x2FF` or a single address
+    fn parse_range(s: &str) -> Result<RangeInclusive<usize>, String> {
+        let invalid = || format!("invalid trace address range '{}'", s);
+        let mut parts = s.splitn(2, '-');
+        let start = parse_addr(parts.next().unwrap_or("").trim()).ok_or_else(invalid)?;
+        let end = match parts.next() {
+            Some(end) => parse_addr(end.trim()).ok_or_else(invalid)?,
+            None => start,
+        };
+        if end < start {
+            return Err(format!("trace address range '{}' ends before it starts", s));
+        }
+        Ok(start..=end)
+    }
+
+    pub fn matches(&self, entry: &Entry) -> bool {
+        (self.ranges.is_empty() || self.ranges.iter().any(|r| r.contains(&entry.pc)))
+            && (self.opcodes.is_empty() || self.opcodes.iter().any(|p| p.matches(entry.opcode)))
+    }
+}
+
+/// Writes the instructions passing the filter to a sink
+pub struct TraceLog {
+    sink: Sink,
+    filter: Filter,
+    /// Writing the file failed, tracing stopped
+    failed: bool,
+}
+
+impl TraceLog {
+    pub fn new(sink: Sink, filter: Filter) -> Self {
+        Self {
+            sink,
+            filter,
+            failed: false,
+        }
+    }
+}
+
+impl Tracer for TraceLog {
+    fn trace(&mut self, entry: &Entry) {
+        if self.failed || !self.filter.matches(entry) {
+            return;
+        }
+
+        match &mut self.sink {
+            Sink::Stderr => eprintln!("{}", entry),
+            Sink::File(file) => {
+                let changed = (0..16)
+                    .filter(|x| entry.before[*x] != entry.after[*x])
+                    .fold(0u16, |mask, x| mask | 1 << x);
+
+                let mut record = [0; 26];
+                record[0..2].copy_from_slice(&(entry.pc as u16).to_le_bytes());
+                record[2..4].copy_from_slice(&entry.opcode);
+                record[4..6].copy_from_slice(&changed.to_le_bytes());
+                record[6..22].copy_from_slice(&entry.after);
+                record[22..24].copy_from_slice(&(entry.i as u16).to_le_bytes());
+                record[24] = entry.delay;
+                record[25] = entry.sound;
+
+                if let Err(e) = file.write_all(&record) {
+                    eprintln!("Error: could not write the trace: {}", e);
+                    self.failed = true;
+                }
+            },
+            Sink::Ring { capacity, entries } => {
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(*entry);
+            },
+        }
+    }
+
+    fn crashed(&mut self, error: &MachineError) {
+        if let Sink::Ring { entries, .. } = &self.sink {
+            eprintln!("Last {} instructions before {}:", entries.len(), error);
+            for entry in entries {
+                eprintln!("{}", entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pc: usize, opcode: [u8; 2]) -> Entry {
+        Entry {
+            pc,
+            opcode,
+            instruction: Instruction::parse(opcode[0], opcode[1]).unwrap(),
+            before: [0; 16],
+            after: [0; 16],
+            i: 0,
+            delay: 0,
+            sound: 0,
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        let draw = Pattern::parse("Dxyn").unwrap();
+        assert!(draw.matches([0xD1, 0x25]));
+        assert!(draw.matches([0xDF, 0xF0]));
+        assert!(!draw.matches([0xC1, 0x25]));
+
+        let add = Pattern::parse("8xy4").unwrap();
+        assert!(add.matches([0x81, 0x24]));
+        assert!(!add.matches([0x81, 0x25]));
+
+        let exact = Pattern::parse("00e0").unwrap();
+        assert!(exact.matches([0x00, 0xE0]));
+        assert!(!exact.matches([0x00, 0xEE]));
+
+        assert!(Pattern::parse("8xy").is_none());
+        assert!(Pattern::parse("8xy44").is_none());
+    }
+
+    #[test]
+    fn ranges() {
+        let mut filter = Filter::default();
+        filter.add_ranges("0x200-0x20F, 0x300,$400 - $401").unwrap();
+        assert_eq!(filter.ranges, [0x200..=0x20F, 0x300..=0x300, 0x400..=0x401]);
+
+        for range in ["0x2FF-0x200", "0x200-", "foo", "0x200,"].iter() {
+            assert!(Filter::default().add_ranges(range).is_err(), "{}", range);
+        }
+        assert_eq!(
+            Filter::default().add_ranges("0x2FF-0x200"),
+            Err("trace address range '0x2FF-0x200' ends before it starts".to_string())
+        );
+    }
+
+    #[test]
+    fn filter() {
+        let mut filter = Filter::default();
+        assert!(filter.matches(&entry(0x800, [0x00, 0xE0])));
+
+        filter.add_opcodes("Dxyn,8xy4").unwrap();
+        assert_eq!(filter.opcodes.len(), 2);
+        assert!(filter.matches(&entry(0x800, [0xD0, 0x15])));
+        assert!(filter.matches(&entry(0x800, [0x80, 0x14])));
+        assert!(!filter.matches(&entry(0x800, [0x00, 0xE0])));
+
+        filter.add_ranges("0x200-0x2FF").unwrap();
+        assert!(filter.matches(&entry(0x2FF, [0xD0, 0x15])));
+        assert!(!filter.matches(&entry(0x300, [0xD0, 0x15])));
+        assert!(!filter.matches(&entry(0x200, [0x00, 0xE0])));
+
+        assert_eq!(
+            Filter::default().add_opcodes("Dxyn,8xy"),
+            Err("invalid trace opcode '8xy'".to_string())
+        );
+    }
+}